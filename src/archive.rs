@@ -0,0 +1,120 @@
+use crate::cron::LivestreamScheduler;
+use crate::data;
+use axum::http::HeaderMap;
+use hololive_livestream_notifier_rs::pubsub;
+use mongodb::bson::{self, oid::ObjectId};
+use quick_xml::de::from_str;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Keep a received body and its headers in the `payloadArchive` collection.
+///
+/// Returns the id of the archived payload, or `None` if it could not be saved. Failing to
+/// archive never stops a delivery from being processed.
+pub async fn archive_payload(headers: &HeaderMap, body: &str) -> Option<ObjectId> {
+    let mut archived_headers = std::collections::HashMap::<String, String>::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        archived_headers
+            .entry(name.to_string())
+            .and_modify(|values| {
+                values.push_str(", ");
+                values.push_str(&value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    let payload = data::models::ArchivedPayload {
+        id: None,
+        body: body.to_string(),
+        headers: archived_headers,
+        received_at: bson::DateTime::now(),
+        outcome: None,
+        outcome_at: None,
+    };
+
+    match data::Mongo::new().await.archive_payload(&payload).await {
+        Ok(id) => id,
+        Err(e) => {
+            println!("Error archiving payload: {}", e);
+            None
+        }
+    }
+}
+
+/// Record what became of an archived payload. Does nothing if it was never archived.
+pub async fn record_outcome(archive_id: Option<ObjectId>, outcome: &str) {
+    let Some(archive_id) = archive_id else {
+        return;
+    };
+
+    if let Err(e) = data::Mongo::new()
+        .await
+        .set_payload_outcome(&archive_id, outcome)
+        .await
+    {
+        println!("Error recording outcome of payload {}: {}", archive_id, e);
+    }
+}
+
+/// Send an archived payload, or the XML file at `source`, through the pipeline again.
+///
+/// `source` is the id of a document in `payloadArchive`, otherwise a path to a file.
+/// Entries are processed even if they were processed before. With `dry_run` nothing is
+/// sent, saved or scheduled. Returns what happened to every notification in the feed.
+pub async fn replay(
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    source: &str,
+    dry_run: bool,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let payload = match ObjectId::parse_str(source) {
+        Ok(id) => {
+            data::Mongo::new()
+                .await
+                .get_archived_payload(&id)
+                .await?
+                .ok_or_else(|| format!("No archived payload with id {}", id))?
+                .body
+        }
+        Err(_) => tokio::fs::read_to_string(source).await?,
+    };
+
+    let yt_feed = from_str::<pubsub::youtube::YoutubeFeed>(&payload)?;
+    println!(
+        "Replaying feed from {} (dry run: {}): {:?}",
+        source, dry_run, yt_feed
+    );
+
+    let mut results = Vec::new();
+    for notification in yt_feed.into_notifications() {
+        let result = match notification {
+            pubsub::youtube::YoutubeNotification::Entry(entry) => {
+                let updated = entry.updated.or(entry.published);
+                crate::process_url(
+                    Arc::clone(&livestream_scheduler),
+                    entry.link.href.as_str(),
+                    updated.unwrap_or_else(chrono::Utc::now).timestamp_millis(),
+                    None,
+                    dry_run,
+                )
+                .await
+                .map(|outcome| format!("[{}] {}", entry.video_id, outcome))
+                .map_err(|e| format!("[{}] {}", entry.video_id, e))
+            }
+            pubsub::youtube::YoutubeNotification::Deleted(deleted_entry) => {
+                crate::process_deleted_entry(
+                    Arc::clone(&livestream_scheduler),
+                    &deleted_entry,
+                    dry_run,
+                )
+                .await
+                .map(|outcome| format!("[{}] {}", deleted_entry.reference, outcome))
+                .map_err(|e| format!("[{}] {}", deleted_entry.reference, e))
+            }
+        };
+
+        results.push(result.unwrap_or_else(|e| format!("Error: {}", e)));
+    }
+
+    Ok(results)
+}
@@ -0,0 +1,36 @@
+use dotenv::dotenv;
+use hololive_livestream_notifier_rs::config::env_or;
+use hololive_livestream_notifier_rs::hub::LocalHub;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Run a [`LocalHub`] on `LOCAL_HUB_PORT` (default 8081).
+///
+/// Point a feed's `topicURL` at `http://localhost:8081/xml/feeds/videos.xml?channel_id=...`
+/// and publish entries with
+/// `curl -X POST 'http://localhost:8081/publish?channel_id=...&video_id=...&title=...'`
+/// or tombstones with the same query on `/delete`.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv().ok();
+
+    let port: u16 = env_or("LOCAL_HUB_PORT", 8081);
+    let base_url = std::env::var("LOCAL_HUB_URL")
+        .ok()
+        .filter(|url| !url.trim().is_empty())
+        .unwrap_or_else(|| format!("http://localhost:{}/", port));
+
+    let hub = Arc::new(LocalHub::new(reqwest::Url::parse(&base_url)?));
+    println!(
+        "Local hub listening on {} (hub URL {})",
+        port,
+        hub.hub_url()
+    );
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    axum::Server::bind(&addr)
+        .serve(hub.router().into_make_service())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use dotenv::dotenv;
+use hololive_livestream_notifier_rs::config::{env_or, env_parse};
+use hololive_livestream_notifier_rs::youtube_mock::MockYoutube;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Run a [`MockYoutube`] on `YOUTUBE_MOCK_PORT` (default 8082), answering from the fixtures
+/// in `YOUTUBE_MOCK_FIXTURES` (default `fixtures/youtube`).
+///
+/// Run the bot with `YOUTUBE_API_BASE_URL=http://localhost:8082` to use it. With
+/// `YOUTUBE_MOCK_QUOTA` set, every API key gets that many units before `quotaExceeded`.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv().ok();
+
+    let port: u16 = env_or("YOUTUBE_MOCK_PORT", 8082);
+    let fixtures_dir = std::env::var("YOUTUBE_MOCK_FIXTURES")
+        .ok()
+        .filter(|dir| !dir.trim().is_empty())
+        .unwrap_or_else(|| "fixtures/youtube".to_string());
+
+    let mut mock = MockYoutube::new().fixtures_dir(&fixtures_dir);
+    if let Some(quota) = env_parse("YOUTUBE_MOCK_QUOTA") {
+        mock = mock.quota_per_key(quota);
+    }
+    println!(
+        "Mock YouTube API listening on {} (fixtures in {})",
+        port, fixtures_dir
+    );
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    axum::Server::bind(&addr)
+        .serve(Arc::new(mock).router().into_make_service())
+        .await?;
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use crate::{Context, Error};
+use std::sync::Arc;
 
 /// Show this help menu
 #[poise::command(prefix_command, track_edits, slash_command)]
@@ -30,3 +31,85 @@ pub async fn ping(ctx: Context<'_>) -> Result<(), Error> {
     ctx.say("Pong").await?;
     Ok(())
 }
+
+/// Process a received feed again
+///
+/// Takes the id of a payload in the `payloadArchive` collection or the path of an XML
+/// file on the bot's machine.
+/// ```
+/// replay <source> [dry_run]
+/// ```
+#[poise::command(prefix_command, slash_command, check = "is_developer")]
+pub async fn replay(
+    ctx: Context<'_>,
+    #[description = "Archived payload id or path to an XML file"] source: String,
+    #[description = "Only report what would be done"] dry_run: Option<bool>,
+) -> Result<(), Error> {
+    ctx.defer().await?;
+
+    let results = crate::archive::replay(
+        Arc::clone(&ctx.data().livestream_scheduler),
+        &source,
+        dry_run.unwrap_or(false),
+    )
+    .await?;
+
+    if results.is_empty() {
+        ctx.say("The feed has no entries").await?;
+    }
+    for reply in crate::discord::split_message(&results) {
+        ctx.say(reply).await?;
+    }
+    Ok(())
+}
+
+/// Show how much of today's YouTube Data API quota was spent
+///
+/// The quota resets at midnight Pacific time. Also shows how many duplicate deliveries
+/// were dropped without a lookup since the bot started.
+/// ```
+/// quota
+/// ```
+#[poise::command(prefix_command, slash_command, check = "is_developer")]
+pub async fn quota(ctx: Context<'_>) -> Result<(), Error> {
+    let usage = crate::youtube::quota_usage().await?;
+    let budget = crate::youtube::QuotaBudget::from_env();
+
+    let mut reply = format!(
+        "YouTube API quota for {} (Pacific): {}/{} units in {} request(s). Low-priority requests are deferred after {} units; {} were deferred.",
+        usage.day,
+        usage.units,
+        budget.units,
+        usage.requests,
+        budget.low_priority_units,
+        usage.deferred
+    );
+    for api_key in crate::youtube::api_keys() {
+        let key = crate::youtube::key_label(&api_key);
+        let key_usage = usage.keys.get(&key).cloned().unwrap_or_default();
+        reply.push_str(&format!(
+            "\n- {}: {} units in {} request(s){}",
+            key,
+            key_usage.units,
+            key_usage.requests,
+            if key_usage.exhausted_at.is_some() {
+                ", out of quota"
+            } else {
+                ""
+            }
+        ));
+    }
+    reply.push_str(&format!(
+        "\n{} duplicate deliveries were dropped since the bot started.",
+        ctx.data().deduplicator.duplicates_dropped()
+    ));
+
+    ctx.say(reply).await?;
+    Ok(())
+}
+
+/// Only let the developer (`DEVELOPER_USER_ID`) run a command.
+async fn is_developer(ctx: Context<'_>) -> Result<bool, Error> {
+    let developer_user_id = std::env::var("DEVELOPER_USER_ID")?.parse::<u64>()?;
+    Ok(ctx.author().id == developer_user_id)
+}
@@ -0,0 +1,32 @@
+use std::str::FromStr;
+
+/// The value of the env var `name`, or `None` when it is unset or does not parse.
+pub fn env_parse<T: FromStr>(name: &str) -> Option<T> {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+}
+
+/// The value of the env var `name`, or `default` when it is unset or does not parse.
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env_parse(name).unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_or_parses_the_var() {
+        std::env::set_var("CONFIG_TEST_SET", "42");
+        assert_eq!(env_or("CONFIG_TEST_SET", 7u64), 42);
+    }
+
+    #[test]
+    fn env_or_falls_back_when_unset_or_invalid() {
+        std::env::set_var("CONFIG_TEST_INVALID", "soon");
+        assert_eq!(env_or("CONFIG_TEST_INVALID", 7u64), 7);
+        assert_eq!(env_or("CONFIG_TEST_UNSET", 7u64), 7);
+        assert_eq!(env_parse::<u64>("CONFIG_TEST_UNSET"), None);
+    }
+}
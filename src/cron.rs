@@ -8,7 +8,7 @@ pub struct LivestreamScheduler {
     jobs: HashMap<String, Uuid>,
 }
 
-type AsyncFn = std::pin::Pin<Box<dyn Future<Output = ()> + Send>>;
+pub type AsyncFn = std::pin::Pin<Box<dyn Future<Output = ()> + Send>>;
 
 impl LivestreamScheduler {
     pub async fn new() -> Self {
@@ -36,7 +36,7 @@ impl LivestreamScheduler {
         Ok(())
     }
 
-    async fn cancel_livestream_notification(&mut self, key: &str) {
+    pub async fn cancel_livestream_notification(&mut self, key: &str) {
         if let Some(job_uuid) = self.jobs.get(key) {
             self.scheduler
                 .remove(job_uuid)
@@ -7,6 +7,8 @@ use mongodb::{options::ClientOptions, Client};
 use regex::Regex;
 pub mod models;
 
+/// Clones share the driver's connection pool.
+#[derive(Clone)]
 pub struct Mongo {
     client: Client,
 }
@@ -39,12 +41,61 @@ impl Mongo {
         typed_collection.find_one(doc! { "url": url }, None).await
     }
 
+    /// The livestream whose watch URL has `video_id` as its `v` parameter, however the URL
+    /// is written.
+    pub async fn get_livestream_by_video_id(
+        &self,
+        video_id: &str,
+    ) -> mongodb::error::Result<Option<models::Livestream>> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::Livestream>("scheduledLivestreams");
+        let pattern = format!("[?&]v={}(&|#|$)", regex::escape(video_id));
+
+        typed_collection
+            .find_one(doc! { "url": { "$regex": pattern } }, None)
+            .await
+    }
+
     pub async fn get_livestreams(&self) -> mongodb::error::Result<Vec<models::Livestream>> {
         let typed_collection = self
             .client
             .database("hololive-en")
             .collection::<models::Livestream>("scheduledLivestreams");
-        let filter = doc! {};
+        let filter = doc! { "deletedAt": null };
+        let find_options = FindOptions::builder().sort(doc! { "date": 1 }).build();
+        let cursor = typed_collection.find(filter, find_options);
+
+        let livestreams: Vec<models::Livestream> = cursor
+            .await?
+            .filter_map(|doc| async move {
+                match doc {
+                    Ok(doc) => Some(doc),
+                    Err(e) => {
+                        println!("Error parsing livestream: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect()
+            .await;
+        Ok(livestreams)
+    }
+
+    /// Livestreams that are not deleted and are scheduled after `after`, soonest first.
+    pub async fn get_upcoming_livestreams(
+        &self,
+        after: DateTime<Utc>,
+    ) -> mongodb::error::Result<Vec<models::Livestream>> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::Livestream>("scheduledLivestreams");
+        let filter = doc! {
+            "deletedAt": null,
+            "date": { "$gt": bson::DateTime::from_chrono(after) },
+        };
         let find_options = FindOptions::builder().sort(doc! { "date": 1 }).build();
         let cursor = typed_collection.find(filter, find_options);
 
@@ -122,6 +173,438 @@ impl Mongo {
             .await;
         Ok(feeds)
     }
+
+    /// Watch the `feeds` collection for changes, resuming after `resume_token` if given.
+    ///
+    /// Updates carry the current version of the document. Needs a replica set.
+    pub async fn watch_feeds(
+        &self,
+        resume_token: Option<mongodb::change_stream::event::ResumeToken>,
+    ) -> mongodb::error::Result<
+        mongodb::change_stream::ChangeStream<
+            mongodb::change_stream::event::ChangeStreamEvent<models::Feed>,
+        >,
+    > {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::Feed>("feeds");
+        let options = mongodb::options::ChangeStreamOptions::builder()
+            .full_document(Some(mongodb::options::FullDocumentType::UpdateLookup))
+            .resume_after(resume_token)
+            .build();
+
+        typed_collection.watch(None, options).await
+    }
+
+    pub async fn get_subscriptions(&self) -> mongodb::error::Result<Vec<models::Subscription>> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::Subscription>("subscriptions");
+        let cursor = typed_collection.find(doc! {}, None);
+
+        let subscriptions: Vec<models::Subscription> = cursor
+            .await?
+            .filter_map(|doc| async move {
+                match doc {
+                    Ok(doc) => Some(doc),
+                    Err(e) => {
+                        println!("Error parsing subscription: {}", e);
+                        None
+                    }
+                }
+            })
+            .collect()
+            .await;
+        Ok(subscriptions)
+    }
+
+    pub async fn upsert_subscription(
+        &self,
+        subscription: &models::Subscription,
+    ) -> mongodb::error::Result<()> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::Subscription>("subscriptions");
+        let filter = doc! { "topic": &subscription.topic };
+        let update = doc! { "$set": bson::to_bson(&subscription).unwrap() };
+        let options = Some(
+            mongodb::options::UpdateOptions::builder()
+                .upsert(Some(true))
+                .build(),
+        );
+        typed_collection.update_one(filter, update, options).await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_subscription(&self, topic: &str) -> mongodb::error::Result<()> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::Subscription>("subscriptions");
+        typed_collection
+            .delete_one(doc! { "topic": topic }, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn enqueue_delivery(
+        &self,
+        job: &models::DeliveryJob,
+    ) -> mongodb::error::Result<bson::Bson> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::DeliveryJob>("deliveryJobs");
+        let insert_result = typed_collection.insert_one(job, None).await?;
+
+        Ok(insert_result.inserted_id)
+    }
+
+    /// Lock the oldest due delivery job for `lock_duration` and return it.
+    pub async fn claim_delivery(
+        &self,
+        lock_duration: chrono::Duration,
+    ) -> mongodb::error::Result<Option<models::DeliveryJob>> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::DeliveryJob>("deliveryJobs");
+        let now = bson::DateTime::now();
+        let filter = doc! {
+            "nextAttemptAt": { "$lte": now },
+            "$or": [
+                { "lockedUntil": null },
+                { "lockedUntil": { "$lte": now } },
+            ],
+        };
+        let locked_until =
+            bson::DateTime::from_millis(now.timestamp_millis() + lock_duration.num_milliseconds());
+        let update = doc! { "$set": { "lockedUntil": locked_until } };
+        let options = mongodb::options::FindOneAndUpdateOptions::builder()
+            .sort(doc! { "nextAttemptAt": 1 })
+            .return_document(mongodb::options::ReturnDocument::After)
+            .build();
+
+        typed_collection
+            .find_one_and_update(filter, update, options)
+            .await
+    }
+
+    pub async fn complete_delivery(&self, id: &bson::oid::ObjectId) -> mongodb::error::Result<()> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::DeliveryJob>("deliveryJobs");
+        typed_collection
+            .delete_one(doc! { "_id": id }, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Release a failed job so it is picked up again at `next_attempt_at`.
+    pub async fn retry_delivery(
+        &self,
+        id: &bson::oid::ObjectId,
+        attempts: u32,
+        next_attempt_at: bson::DateTime,
+        error: &str,
+    ) -> mongodb::error::Result<()> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::DeliveryJob>("deliveryJobs");
+        let update = doc! {
+            "$set": {
+                "attempts": attempts,
+                "nextAttemptAt": next_attempt_at,
+                "lastError": error,
+                "lockedUntil": null,
+            }
+        };
+        typed_collection
+            .update_one(doc! { "_id": id }, update, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Move a job that will not be retried to the `deadLetterDeliveries` collection.
+    pub async fn dead_letter_delivery(
+        &self,
+        job: &models::DeliveryJob,
+    ) -> mongodb::error::Result<()> {
+        let database = self.client.database("hololive-en");
+
+        database
+            .collection::<models::DeliveryJob>("deadLetterDeliveries")
+            .insert_one(job, None)
+            .await?;
+        database
+            .collection::<models::DeliveryJob>("deliveryJobs")
+            .delete_one(doc! { "_id": job.id }, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn archive_payload(
+        &self,
+        payload: &models::ArchivedPayload,
+    ) -> mongodb::error::Result<Option<bson::oid::ObjectId>> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::ArchivedPayload>("payloadArchive");
+        let insert_result = typed_collection.insert_one(payload, None).await?;
+
+        Ok(insert_result.inserted_id.as_object_id())
+    }
+
+    pub async fn get_archived_payload(
+        &self,
+        id: &bson::oid::ObjectId,
+    ) -> mongodb::error::Result<Option<models::ArchivedPayload>> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::ArchivedPayload>("payloadArchive");
+
+        typed_collection.find_one(doc! { "_id": id }, None).await
+    }
+
+    pub async fn set_payload_outcome(
+        &self,
+        id: &bson::oid::ObjectId,
+        outcome: &str,
+    ) -> mongodb::error::Result<()> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::ArchivedPayload>("payloadArchive");
+        let update = doc! {
+            "$set": {
+                "outcome": outcome,
+                "outcomeAt": bson::DateTime::now(),
+            }
+        };
+        typed_collection
+            .update_one(doc! { "_id": id }, update, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Claim `key` for processing, unless it was processed within `window` or another
+    /// claim on it is younger than `lease`.
+    ///
+    /// Returns `false` for a duplicate. A claim that was never marked processed with
+    /// [`Mongo::complete_processed_entry`], e.g. because the bot stopped while processing
+    /// it, can be taken over once its lease is up.
+    pub async fn claim_processed_entry(
+        &self,
+        key: &str,
+        window: chrono::Duration,
+        lease: chrono::Duration,
+    ) -> mongodb::error::Result<bool> {
+        let collection = self
+            .client
+            .database("hololive-en")
+            .collection::<bson::Document>("processedEntries");
+        let now = bson::DateTime::now();
+
+        match collection
+            .insert_one(doc! { "_id": key, "claimedAt": now }, None)
+            .await
+        {
+            Ok(_) => return Ok(true),
+            Err(e) if !is_duplicate_key_error(&e) => return Err(e),
+            Err(_) => {}
+        }
+
+        let window_cutoff =
+            bson::DateTime::from_millis(now.timestamp_millis() - window.num_milliseconds());
+        let lease_cutoff =
+            bson::DateTime::from_millis(now.timestamp_millis() - lease.num_milliseconds());
+        let update_result = collection
+            .update_one(
+                doc! {
+                    "_id": key,
+                    "$or": [
+                        { "processedAt": { "$lt": window_cutoff } },
+                        { "processedAt": null, "claimedAt": { "$lt": lease_cutoff } },
+                    ],
+                },
+                doc! {
+                    "$set": { "claimedAt": now },
+                    "$unset": { "processedAt": "" },
+                },
+                None,
+            )
+            .await?;
+
+        Ok(update_result.modified_count == 1)
+    }
+
+    /// Record that the entry claimed as `key` was processed.
+    pub async fn complete_processed_entry(&self, key: &str) -> mongodb::error::Result<()> {
+        let collection = self
+            .client
+            .database("hololive-en")
+            .collection::<bson::Document>("processedEntries");
+        collection
+            .update_one(
+                doc! { "_id": key },
+                doc! { "$set": { "processedAt": bson::DateTime::now() } },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Have MongoDB delete claims `expire_after` after they were made.
+    pub async fn create_processed_entries_index(
+        &self,
+        expire_after: chrono::Duration,
+    ) -> mongodb::error::Result<()> {
+        let collection = self
+            .client
+            .database("hololive-en")
+            .collection::<bson::Document>("processedEntries");
+        let index = mongodb::IndexModel::builder()
+            .keys(doc! { "claimedAt": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .expire_after(expire_after.to_std().ok())
+                    .build(),
+            )
+            .build();
+        collection.create_index(index, None).await?;
+
+        Ok(())
+    }
+
+    /// Whether `key` was ever claimed with [`Mongo::claim_processed_entry`].
+    pub async fn is_processed_entry(&self, key: &str) -> mongodb::error::Result<bool> {
+        let collection = self
+            .client
+            .database("hololive-en")
+            .collection::<bson::Document>("processedEntries");
+        let processed_entry = collection.find_one(doc! { "_id": key }, None).await?;
+
+        Ok(processed_entry.is_some())
+    }
+
+    /// Forget a claim so that a failed entry can be processed again.
+    pub async fn release_processed_entry(&self, key: &str) -> mongodb::error::Result<()> {
+        let collection = self
+            .client
+            .database("hololive-en")
+            .collection::<bson::Document>("processedEntries");
+        collection.delete_one(doc! { "_id": key }, None).await?;
+
+        Ok(())
+    }
+
+    /// Add a request made with the API key labelled `key` that cost `units` to the quota
+    /// spent on `day`.
+    pub async fn add_quota_usage(
+        &self,
+        day: &str,
+        key: &str,
+        units: i64,
+    ) -> mongodb::error::Result<()> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::QuotaUsage>("youtubeQuota");
+        let options = mongodb::options::UpdateOptions::builder()
+            .upsert(true)
+            .build();
+        typed_collection
+            .update_one(
+                doc! { "_id": day },
+                doc! {
+                    "$inc": {
+                        "units": units,
+                        "requests": 1,
+                        format!("keys.{}.units", key): units,
+                        format!("keys.{}.requests", key): 1,
+                    }
+                },
+                options,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Take the API key labelled `key` out of rotation for the rest of `day`.
+    pub async fn set_key_exhausted(&self, day: &str, key: &str) -> mongodb::error::Result<()> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::QuotaUsage>("youtubeQuota");
+        let options = mongodb::options::UpdateOptions::builder()
+            .upsert(true)
+            .build();
+        typed_collection
+            .update_one(
+                doc! { "_id": day },
+                doc! { "$set": { format!("keys.{}.exhaustedAt", key): bson::DateTime::now() } },
+                options,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Count a low-priority request that was deferred on `day`.
+    pub async fn add_deferred_request(&self, day: &str) -> mongodb::error::Result<()> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::QuotaUsage>("youtubeQuota");
+        let options = mongodb::options::UpdateOptions::builder()
+            .upsert(true)
+            .build();
+        typed_collection
+            .update_one(
+                doc! { "_id": day },
+                doc! { "$inc": { "deferred": 1 } },
+                options,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_quota_usage(&self, day: &str) -> mongodb::error::Result<models::QuotaUsage> {
+        let typed_collection = self
+            .client
+            .database("hololive-en")
+            .collection::<models::QuotaUsage>("youtubeQuota");
+        let quota_usage = typed_collection.find_one(doc! { "_id": day }, None).await?;
+
+        Ok(quota_usage.unwrap_or_else(|| models::QuotaUsage {
+            day: day.to_string(),
+            ..Default::default()
+        }))
+    }
+}
+
+fn is_duplicate_key_error(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(write_error))
+            if write_error.code == 11000
+    )
 }
 
 pub struct Scraper {
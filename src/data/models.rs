@@ -1,4 +1,6 @@
+use hololive_livestream_notifier_rs::pubsub;
 use mongodb::bson::doc;
+use mongodb::bson::oid::ObjectId;
 use mongodb::bson::DateTime;
 use serde::Deserializer;
 use serde::{Deserialize, Serialize};
@@ -41,19 +43,39 @@ pub struct Livestream {
     pub date: DateTime,
     pub title: String,
     pub updated: DateTime,
+    /// Messages announcing the stream, so follow-ups reach the same channels.
+    #[serde(default)]
+    pub announcements: Vec<Announcement>,
+    /// Set when YouTube sent a tombstone for the video.
+    #[serde(rename = "deletedAt", default)]
+    pub deleted_at: Option<DateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Announcement {
+    #[serde(rename = "channelId")]
+    pub channel_id: u64,
+    #[serde(rename = "messageId")]
+    pub message_id: u64,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Feed {
+    #[serde(rename = "_id", default)]
+    pub id: Option<ObjectId>,
     #[serde(rename = "firstName")]
     pub first_name: String,
     #[serde(rename = "lastName")]
     pub last_name: String,
     #[serde(rename = "topicURL", deserialize_with = "de_url")]
     pub topic_url: reqwest::Url,
-    pub group: String,
-    #[serde(default)]
-    pub generation: u8,
+    /// Whether the talent still streams. Inactive channels are not watched for silence.
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
 }
 
 fn de_url<'de, D>(deserializer: D) -> Result<reqwest::Url, D::Error>
@@ -64,3 +86,136 @@ where
     let url = reqwest::Url::parse(&s).map_err(serde::de::Error::custom)?;
     Ok(url)
 }
+
+/// A WebSub subscription as saved in the `subscriptions` collection.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Subscription {
+    /// Missing for subscriptions made before each had its own callback URL.
+    #[serde(rename = "subscriptionId", default)]
+    pub subscription_id: Option<String>,
+    pub hub: String,
+    pub topic: String,
+    pub secret: String,
+    pub state: pubsub::SubscriptionState,
+    #[serde(default)]
+    pub intent: Option<pubsub::Mode>,
+    #[serde(rename = "requestedAt", default)]
+    pub requested_at: Option<DateTime>,
+    #[serde(rename = "leaseExpiresAt", default)]
+    pub lease_expires_at: Option<DateTime>,
+    #[serde(rename = "lastChallengeAt", default)]
+    pub last_challenge_at: Option<DateTime>,
+    #[serde(rename = "lastDeliveryAt", default)]
+    pub last_delivery_at: Option<DateTime>,
+}
+
+impl From<&pubsub::Subscription> for Subscription {
+    fn from(subscription: &pubsub::Subscription) -> Self {
+        Self {
+            subscription_id: Some(subscription.id.clone()),
+            hub: subscription.hub.clone(),
+            topic: subscription.topic.to_string(),
+            secret: subscription.secret.clone(),
+            state: subscription.state,
+            intent: subscription.intent,
+            requested_at: subscription.requested_at.map(DateTime::from_chrono),
+            lease_expires_at: subscription.lease_expires_at.map(DateTime::from_chrono),
+            last_challenge_at: subscription.last_challenge_at.map(DateTime::from_chrono),
+            last_delivery_at: subscription.last_delivery_at.map(DateTime::from_chrono),
+        }
+    }
+}
+
+impl TryFrom<Subscription> for pubsub::Subscription {
+    type Error = String;
+
+    fn try_from(subscription: Subscription) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: subscription
+                .subscription_id
+                .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()),
+            hub: subscription.hub,
+            topic: reqwest::Url::parse(&subscription.topic)
+                .map_err(|e| format!("Invalid topic ({}): {}", subscription.topic, e))?,
+            secret: subscription.secret,
+            state: subscription.state,
+            intent: subscription.intent,
+            requested_at: subscription.requested_at.map(DateTime::to_chrono),
+            lease_expires_at: subscription.lease_expires_at.map(DateTime::to_chrono),
+            last_challenge_at: subscription.last_challenge_at.map(DateTime::to_chrono),
+            last_delivery_at: subscription.last_delivery_at.map(DateTime::to_chrono),
+        })
+    }
+}
+
+/// A received WebSub delivery waiting to be processed, as saved in the `deliveryJobs`
+/// collection. Jobs that keep failing are moved to `deadLetterDeliveries`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeliveryJob {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub topic: String,
+    pub payload: String,
+    #[serde(rename = "receivedAt")]
+    pub received_at: DateTime,
+    pub attempts: u32,
+    #[serde(rename = "nextAttemptAt")]
+    pub next_attempt_at: DateTime,
+    /// Set while a worker holds the job; an expired lock makes it available again.
+    #[serde(rename = "lockedUntil", default)]
+    pub locked_until: Option<DateTime>,
+    #[serde(rename = "lastError", default)]
+    pub last_error: Option<String>,
+    #[serde(rename = "deadLetteredAt", default)]
+    pub dead_lettered_at: Option<DateTime>,
+    /// The [`ArchivedPayload`] this job was created from.
+    #[serde(rename = "archiveId", default)]
+    pub archive_id: Option<ObjectId>,
+}
+
+/// A request body received on `/yt-pubsub`, kept as it arrived.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedPayload {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub body: String,
+    pub headers: std::collections::HashMap<String, String>,
+    #[serde(rename = "receivedAt")]
+    pub received_at: DateTime,
+    /// What became of the delivery, e.g. `rejected: ...`, `queued` or `processed`.
+    #[serde(default)]
+    pub outcome: Option<String>,
+    #[serde(rename = "outcomeAt", default)]
+    pub outcome_at: Option<DateTime>,
+}
+
+/// YouTube Data API quota spent on one day, as saved in the `youtubeQuota` collection.
+///
+/// Days start at midnight Pacific time, when YouTube resets the quota.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct QuotaUsage {
+    /// The day, e.g. `2023-09-01`.
+    #[serde(rename = "_id")]
+    pub day: String,
+    #[serde(default)]
+    pub units: i64,
+    #[serde(default)]
+    pub requests: i64,
+    /// Low-priority requests that were not sent because the budget was nearly spent.
+    #[serde(default)]
+    pub deferred: i64,
+    /// Usage of every API key, by the key's label.
+    #[serde(default)]
+    pub keys: std::collections::HashMap<String, KeyUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct KeyUsage {
+    #[serde(default)]
+    pub units: i64,
+    #[serde(default)]
+    pub requests: i64,
+    /// When the key ran out of quota (or hit the rate limit) and was taken out of rotation.
+    #[serde(rename = "exhaustedAt", default)]
+    pub exhausted_at: Option<DateTime>,
+}
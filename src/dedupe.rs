@@ -0,0 +1,78 @@
+use crate::data;
+use hololive_livestream_notifier_rs::config::env_or;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Suppresses repeated processing of the same entry.
+///
+/// Entries are keyed on their video id and `updated` timestamp, which the hub resends
+/// unchanged on retries. Claims are kept in the `processedEntries` collection so they
+/// survive restarts and are shared by every worker. An entry counts as processed once
+/// [`Deduplicator::complete`] is called; until then its claim is only a lease of
+/// `DEDUPE_LEASE_SECS`, so a retry after a crash is not dropped.
+pub struct Deduplicator {
+    window: chrono::Duration,
+    lease: chrono::Duration,
+    duplicates_dropped: AtomicU64,
+}
+
+impl Deduplicator {
+    pub fn new() -> Self {
+        Self {
+            window: chrono::Duration::seconds(env_or("DEDUPE_WINDOW_SECS", 24 * 60 * 60)),
+            lease: chrono::Duration::seconds(env_or("DEDUPE_LEASE_SECS", 5 * 60)),
+            duplicates_dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn key(video_id: &str, updated_ts_ms: i64) -> String {
+        format!("{}:{}", video_id, updated_ts_ms)
+    }
+
+    /// Let MongoDB delete claims once they are older than the window.
+    pub async fn create_expiry_index(&self) {
+        if let Err(e) = data::Mongo::new()
+            .await
+            .create_processed_entries_index(self.window + self.lease)
+            .await
+        {
+            println!("Error creating the processedEntries expiry index: {}", e);
+        }
+    }
+
+    /// Returns `true` if the entry should be processed, `false` if it is a duplicate.
+    pub async fn claim(&self, key: &str) -> mongodb::error::Result<bool> {
+        let claimed = data::Mongo::new()
+            .await
+            .claim_processed_entry(key, self.window, self.lease)
+            .await?;
+
+        if !claimed {
+            let duplicates_dropped = self.duplicates_dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            println!(
+                "Dropped duplicate entry {} ({} duplicates dropped)",
+                key, duplicates_dropped
+            );
+        }
+
+        Ok(claimed)
+    }
+
+    /// Mark a claimed entry as processed, so it is dropped for the rest of the window.
+    pub async fn complete(&self, key: &str) {
+        if let Err(e) = data::Mongo::new().await.complete_processed_entry(key).await {
+            println!("Error completing entry {}: {}", key, e);
+        }
+    }
+
+    /// Undo a claim after processing failed, so that a retry is not dropped.
+    pub async fn release(&self, key: &str) {
+        if let Err(e) = data::Mongo::new().await.release_processed_entry(key).await {
+            println!("Error releasing entry {}: {}", key, e);
+        }
+    }
+
+    /// Duplicates dropped since the bot started.
+    pub fn duplicates_dropped(&self) -> u64 {
+        self.duplicates_dropped.load(Ordering::Relaxed)
+    }
+}
@@ -1,26 +1,63 @@
-use poise::serenity_prelude::{self as serenity, ChannelId};
+use poise::serenity_prelude::{self as serenity, ChannelId, MessageId};
 
+/// Send `message` to every channel named `channel_name`, returning the sent messages.
 pub async fn send_message_to_channel(
     channel_name: &str,
     message: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<Vec<serenity::Message>, Box<dyn std::error::Error + Send + Sync>> {
     let http = serenity::http::client::Http::new(&std::env::var("DISCORD_TOKEN")?);
 
     let channels = get_channels(channel_name).await?;
 
+    let mut messages = Vec::new();
+
     for channel in channels {
-        ChannelId(channel.id.0)
-            .send_message(&http, |m| m.content(message))
-            .await?;
+        messages.push(
+            ChannelId(channel.id.0)
+                .send_message(&http, |m| m.content(message))
+                .await?,
+        );
     }
 
+    Ok(messages)
+}
+
+pub async fn reply_to_message(
+    channel_id: u64,
+    message_id: u64,
+    message: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let http = serenity::http::client::Http::new(&std::env::var("DISCORD_TOKEN")?);
+
+    ChannelId(channel_id)
+        .send_message(&http, |m| {
+            m.content(message)
+                .reference_message((ChannelId(channel_id), MessageId(message_id)))
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Replace the content of a message the bot sent.
+pub async fn edit_message(
+    channel_id: u64,
+    message_id: u64,
+    message: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let http = serenity::http::client::Http::new(&std::env::var("DISCORD_TOKEN")?);
+
+    ChannelId(channel_id)
+        .edit_message(&http, MessageId(message_id), |m| m.content(message))
+        .await?;
+
     Ok(())
 }
 
 pub async fn send_message_to_user(
     user_id: serenity::UserId,
     message: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let http = serenity::http::client::Http::new(&std::env::var("DISCORD_TOKEN")?);
 
     user_id
@@ -34,7 +71,7 @@ pub async fn send_message_to_user(
 
 async fn get_channels(
     channel_name: &str,
-) -> Result<Vec<serenity::model::channel::GuildChannel>, Box<dyn std::error::Error>> {
+) -> Result<Vec<serenity::model::channel::GuildChannel>, Box<dyn std::error::Error + Send + Sync>> {
     let http = serenity::http::client::Http::new(&std::env::var("DISCORD_TOKEN")?);
 
     let guilds = http.get_guilds(None, None).await?;
@@ -54,3 +91,76 @@ async fn get_channels(
 
     Ok(channels)
 }
+
+/// The most characters Discord accepts in one message.
+pub const MAX_MESSAGE_LEN: usize = 2000;
+
+/// Split `lines` into messages of at most [`MAX_MESSAGE_LEN`] characters, breaking between
+/// lines. A line too long for a message of its own is cut short.
+pub fn split_message(lines: &[String]) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut message = String::new();
+
+    for line in lines {
+        let line = if line.chars().count() > MAX_MESSAGE_LEN {
+            let mut line: String = line.chars().take(MAX_MESSAGE_LEN - 1).collect();
+            line.push('…');
+            line
+        } else {
+            line.clone()
+        };
+
+        if !message.is_empty()
+            && message.chars().count() + 1 + line.chars().count() > MAX_MESSAGE_LEN
+        {
+            messages.push(std::mem::take(&mut message));
+        }
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        message.push_str(&line);
+    }
+
+    if !message.is_empty() {
+        messages.push(message);
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_message_keeps_short_replies_in_one_message() {
+        let lines = vec!["first".to_string(), "second".to_string()];
+        assert_eq!(split_message(&lines), vec!["first\nsecond"]);
+    }
+
+    #[test]
+    fn split_message_breaks_between_lines() {
+        let lines = vec!["a".repeat(1500), "b".repeat(1500), "c".repeat(10)];
+
+        let messages = split_message(&lines);
+
+        assert_eq!(
+            messages,
+            vec![
+                "a".repeat(1500),
+                format!("{}\n{}", "b".repeat(1500), "c".repeat(10))
+            ]
+        );
+    }
+
+    #[test]
+    fn split_message_cuts_lines_that_are_too_long() {
+        let lines = vec!["é".repeat(MAX_MESSAGE_LEN + 1)];
+
+        let messages = split_message(&lines);
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].chars().count(), MAX_MESSAGE_LEN);
+        assert!(messages[0].ends_with('…'));
+    }
+}
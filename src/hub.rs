@@ -0,0 +1,464 @@
+use axum::{
+    extract::Query,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Extension, Form, Router,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use quick_xml::escape::escape;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Stand-in for YouTube's hub, for exercising the subscription lifecycle offline.
+///
+/// Serves channel feeds at `/xml/feeds/videos.xml?channel_id=...` that point at the hub,
+/// accepts subscription requests on `/hub` and verifies them against the subscriber's
+/// callback, and delivers entries published through `/publish` or [`LocalHub::publish_entry`]
+/// to every verified subscriber, signed with their `hub.secret`.
+pub struct LocalHub {
+    base_url: Url,
+    client: reqwest::Client,
+    channels: Mutex<HashMap<String, Channel>>,
+}
+
+#[derive(Default)]
+struct Channel {
+    subscribers: HashMap<Url, Subscriber>,
+    /// Newest first.
+    entries: Vec<Entry>,
+}
+
+struct Subscriber {
+    secret: Option<String>,
+    lease_expires_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    video_id: String,
+    title: String,
+    published: DateTime<Utc>,
+    updated: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HubRequest {
+    #[serde(rename = "hub.mode")]
+    pub mode: String,
+    #[serde(rename = "hub.topic")]
+    pub topic: String,
+    #[serde(rename = "hub.callback")]
+    pub callback: String,
+    #[serde(rename = "hub.secret")]
+    pub secret: Option<String>,
+    #[serde(rename = "hub.lease_seconds")]
+    pub lease_seconds: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedParams {
+    channel_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PublishParams {
+    channel_id: String,
+    video_id: String,
+    title: Option<String>,
+}
+
+/// The result of delivering to one subscriber: the status it answered with, or the error.
+pub type DeliveryResult = (Url, Result<StatusCode, String>);
+
+impl LocalHub {
+    /// Same default lease as YouTube's hub.
+    const DEFAULT_LEASE_SECONDS: i64 = 5 * 24 * 60 * 60;
+
+    /// `base_url` is where [`LocalHub::router`] is served.
+    pub fn new(base_url: Url) -> Self {
+        Self {
+            base_url,
+            client: reqwest::Client::new(),
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/xml/feeds/videos.xml", get(feed_handler))
+            .route("/hub", post(hub_handler))
+            .route("/publish", post(publish_handler))
+            .route("/delete", post(delete_handler))
+            .layer(Extension(self))
+    }
+
+    pub fn hub_url(&self) -> Url {
+        self.base_url.join("hub").expect("hub path is a valid URL")
+    }
+
+    pub fn topic_url(&self, channel_id: &str) -> Url {
+        let mut topic = self
+            .base_url
+            .join("xml/feeds/videos.xml")
+            .expect("feed path is a valid URL");
+        topic
+            .query_pairs_mut()
+            .append_pair("channel_id", channel_id);
+        topic
+    }
+
+    /// Callbacks with a verified, unexpired subscription to `channel_id`'s feed.
+    pub async fn subscribers(&self, channel_id: &str) -> Vec<Url> {
+        let now = Utc::now();
+
+        self.channels
+            .lock()
+            .await
+            .get(channel_id)
+            .map(|channel| {
+                channel
+                    .subscribers
+                    .iter()
+                    .filter(|(_, subscriber)| subscriber.lease_expires_at > now)
+                    .map(|(callback, _)| callback.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Validate a subscription request and verify the intent with the subscriber.
+    ///
+    /// Validation errors are returned straight away. Verification happens in the
+    /// background, as with a real hub, once the request has been accepted.
+    pub async fn request(self: Arc<Self>, request: HubRequest) -> Result<(), String> {
+        if request.mode != "subscribe" && request.mode != "unsubscribe" {
+            return Err(format!("Unsupported hub.mode {:?}", request.mode));
+        }
+        let callback = Url::parse(&request.callback)
+            .map_err(|e| format!("Invalid hub.callback {:?}: {}", request.callback, e))?;
+        let channel_id = self
+            .channel_id(&request.topic)
+            .ok_or_else(|| format!("Unknown hub.topic {:?}", request.topic))?;
+
+        tokio::spawn(async move {
+            if let Err(e) = self.verify(&channel_id, callback, request).await {
+                println!("Local hub: {}", e);
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn verify(
+        &self,
+        channel_id: &str,
+        callback: Url,
+        request: HubRequest,
+    ) -> Result<(), String> {
+        let challenge = uuid::Uuid::new_v4().simple().to_string();
+        let lease_seconds = request.lease_seconds.unwrap_or(Self::DEFAULT_LEASE_SECONDS);
+
+        let mut verification_url = callback.clone();
+        {
+            let mut query = verification_url.query_pairs_mut();
+            query
+                .append_pair("hub.mode", &request.mode)
+                .append_pair("hub.topic", &request.topic)
+                .append_pair("hub.challenge", &challenge);
+            if request.mode == "subscribe" {
+                query.append_pair("hub.lease_seconds", &lease_seconds.to_string());
+            }
+        }
+
+        let response = self
+            .client
+            .get(verification_url)
+            .send()
+            .await
+            .map_err(|e| format!("Error verifying {} for {}: {}", request.mode, callback, e))?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+
+        if !status.is_success() || body != challenge {
+            return Err(format!(
+                "{} not verified by {} ({}, echoed {:?})",
+                request.mode, callback, status, body
+            ));
+        }
+
+        let mut channels = self.channels.lock().await;
+        let channel = channels.entry(channel_id.to_string()).or_default();
+        if request.mode == "subscribe" {
+            channel.subscribers.insert(
+                callback.clone(),
+                Subscriber {
+                    secret: request.secret,
+                    lease_expires_at: Utc::now() + chrono::Duration::seconds(lease_seconds),
+                },
+            );
+        } else {
+            channel.subscribers.remove(&callback);
+        }
+
+        println!(
+            "Local hub: verified {} of {} to {:?}",
+            request.mode, callback, channel_id
+        );
+        Ok(())
+    }
+
+    /// Add a video to `channel_id`'s feed and deliver it to the subscribers.
+    ///
+    /// Publishing a `video_id` again delivers it as an update.
+    pub async fn publish_entry(
+        &self,
+        channel_id: &str,
+        video_id: &str,
+        title: &str,
+    ) -> Vec<DeliveryResult> {
+        let now = Utc::now();
+
+        let entry = {
+            let mut channels = self.channels.lock().await;
+            let channel = channels.entry(channel_id.to_string()).or_default();
+            let published = channel
+                .entries
+                .iter()
+                .find(|entry| entry.video_id == video_id)
+                .map_or(now, |entry| entry.published);
+
+            channel.entries.retain(|entry| entry.video_id != video_id);
+            let entry = Entry {
+                video_id: video_id.to_string(),
+                title: title.to_string(),
+                published,
+                updated: now,
+            };
+            channel.entries.insert(0, entry.clone());
+            entry
+        };
+
+        let feed = self.feed_xml(channel_id, &[entry]);
+        self.distribute(channel_id, feed).await
+    }
+
+    /// Remove a video from `channel_id`'s feed and deliver a tombstone for it.
+    pub async fn publish_deletion(&self, channel_id: &str, video_id: &str) -> Vec<DeliveryResult> {
+        if let Some(channel) = self.channels.lock().await.get_mut(channel_id) {
+            channel.entries.retain(|entry| entry.video_id != video_id);
+        }
+
+        let feed = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns:at="http://purl.org/atompub/tombstones/1.0" xmlns="http://www.w3.org/2005/Atom">
+  <at:deleted-entry ref="yt:video:{video_id}" when="{when}">
+    <link href="https://www.youtube.com/watch?v={video_id}"/>
+    <at:by>
+      <name>{channel_id}</name>
+      <uri>https://www.youtube.com/channel/{channel_id}</uri>
+    </at:by>
+  </at:deleted-entry>
+</feed>
+"#,
+            video_id = escape(video_id),
+            channel_id = escape(channel_id),
+            when = Utc::now().to_rfc3339(),
+        );
+        self.distribute(channel_id, feed).await
+    }
+
+    /// POST `feed` to every subscriber of `channel_id`, signed the way YouTube's hub does.
+    async fn distribute(&self, channel_id: &str, feed: String) -> Vec<DeliveryResult> {
+        let now = Utc::now();
+        let subscribers: Vec<_> = self
+            .channels
+            .lock()
+            .await
+            .get(channel_id)
+            .map(|channel| {
+                channel
+                    .subscribers
+                    .iter()
+                    .filter(|(_, subscriber)| subscriber.lease_expires_at > now)
+                    .map(|(callback, subscriber)| (callback.clone(), subscriber.secret.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let link = format!(
+            "<{}>; rel=hub, <{}>; rel=self",
+            self.hub_url(),
+            self.topic_url(channel_id)
+        );
+
+        let mut results = Vec::new();
+        for (callback, secret) in subscribers {
+            let mut request = self
+                .client
+                .post(callback.clone())
+                .header(header::CONTENT_TYPE, "application/atom+xml")
+                .header(header::LINK, &link)
+                .body(feed.clone());
+
+            if let Some(secret) = secret {
+                let mut mac =
+                    <Hmac<sha1::Sha1> as hmac::digest::KeyInit>::new_from_slice(secret.as_bytes())
+                        .expect("HMAC accepts keys of any size");
+                mac.update(feed.as_bytes());
+                let signature = hex::encode(mac.finalize().into_bytes());
+                request = request.header("X-Hub-Signature", format!("sha1={}", signature));
+            }
+
+            let result = request
+                .send()
+                .await
+                .map(|response| response.status())
+                .map_err(|e| e.to_string());
+            println!("Local hub: delivered to {}: {:?}", callback, result);
+            results.push((callback, result));
+        }
+
+        results
+    }
+
+    /// The channel a topic URL served by this hub belongs to.
+    fn channel_id(&self, topic: &str) -> Option<String> {
+        let topic = Url::parse(topic).ok()?;
+        if topic.origin() != self.base_url.origin() {
+            return None;
+        }
+
+        topic
+            .query_pairs()
+            .find(|(name, _)| name == "channel_id")
+            .map(|(_, channel_id)| channel_id.into_owned())
+    }
+
+    fn feed_xml(&self, channel_id: &str, entries: &[Entry]) -> String {
+        let entries: String = entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    r#"  <entry>
+    <id>yt:video:{video_id}</id>
+    <yt:videoId>{video_id}</yt:videoId>
+    <yt:channelId>{channel_id}</yt:channelId>
+    <title>{title}</title>
+    <link rel="alternate" href="https://www.youtube.com/watch?v={video_id}"/>
+    <author>
+      <name>{channel_id}</name>
+      <uri>https://www.youtube.com/channel/{channel_id}</uri>
+    </author>
+    <published>{published}</published>
+    <updated>{updated}</updated>
+  </entry>
+"#,
+                    video_id = escape(&entry.video_id),
+                    channel_id = escape(channel_id),
+                    title = escape(&entry.title),
+                    published = entry.published.to_rfc3339(),
+                    updated = entry.updated.to_rfc3339(),
+                )
+            })
+            .collect();
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns:yt="http://www.youtube.com/xml/schemas/2015" xmlns="http://www.w3.org/2005/Atom">
+  <link rel="hub" href="{hub}"/>
+  <link rel="self" href="{topic}"/>
+  <title>YouTube video feed</title>
+  <updated>{updated}</updated>
+{entries}</feed>
+"#,
+            hub = escape(self.hub_url().as_str()),
+            topic = escape(self.topic_url(channel_id).as_str()),
+            updated = Utc::now().to_rfc3339(),
+        )
+    }
+}
+
+async fn feed_handler(
+    Extension(hub): Extension<Arc<LocalHub>>,
+    Query(params): Query<FeedParams>,
+) -> impl IntoResponse {
+    let entries = hub
+        .channels
+        .lock()
+        .await
+        .get(&params.channel_id)
+        .map(|channel| channel.entries.iter().take(15).cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        "application/atom+xml".parse().unwrap(),
+    );
+    let link = format!(
+        "<{}>; rel=hub, <{}>; rel=self",
+        hub.hub_url(),
+        hub.topic_url(&params.channel_id)
+    );
+    if let Ok(link) = link.parse() {
+        headers.insert(header::LINK, link);
+    }
+
+    (headers, hub.feed_xml(&params.channel_id, &entries))
+}
+
+async fn hub_handler(
+    Extension(hub): Extension<Arc<LocalHub>>,
+    Form(request): Form<HubRequest>,
+) -> (StatusCode, String) {
+    println!("Local hub: received {:?}", request);
+
+    match hub.request(request).await {
+        Ok(()) => (StatusCode::ACCEPTED, String::new()),
+        Err(e) => (StatusCode::BAD_REQUEST, e),
+    }
+}
+
+async fn publish_handler(
+    Extension(hub): Extension<Arc<LocalHub>>,
+    Query(params): Query<PublishParams>,
+) -> String {
+    let title = params
+        .title
+        .unwrap_or_else(|| format!("Synthetic video {}", params.video_id));
+    let results = hub
+        .publish_entry(&params.channel_id, &params.video_id, &title)
+        .await;
+
+    describe_results(&results)
+}
+
+async fn delete_handler(
+    Extension(hub): Extension<Arc<LocalHub>>,
+    Query(params): Query<PublishParams>,
+) -> String {
+    let results = hub
+        .publish_deletion(&params.channel_id, &params.video_id)
+        .await;
+
+    describe_results(&results)
+}
+
+fn describe_results(results: &[DeliveryResult]) -> String {
+    if results.is_empty() {
+        return "No subscribers\n".to_string();
+    }
+
+    results
+        .iter()
+        .map(|(callback, result)| match result {
+            Ok(status) => format!("{}: {}\n", callback, status),
+            Err(e) => format!("{}: {}\n", callback, e),
+        })
+        .collect()
+}
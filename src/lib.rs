@@ -1 +1,5 @@
+pub mod config;
+pub mod hub;
 pub mod pubsub;
+pub mod youtube_api;
+pub mod youtube_mock;
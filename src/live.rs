@@ -0,0 +1,144 @@
+use crate::cron::{AsyncFn, LivestreamScheduler};
+use crate::data;
+use crate::youtube;
+use chrono::Utc;
+use hololive_livestream_notifier_rs::config::{env_or, env_parse};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Announce the livestream at `livestream_url` once it has actually started.
+///
+/// Started at the scheduled time, this looks the stream up every `LIVE_CHECK_INTERVAL_SECS`
+/// until `actualStartTime` is set, then sends the live message. If `LIVE_LATE_MINUTES` is
+/// set and the stream is that late, the announcements get a "running late" reply. Gives up
+/// after `LIVE_CHECK_TIMEOUT_SECS`, or when the stream is deleted or rescheduled. If no API
+/// key has quota left, the live message is sent straight away, as it was before.
+///
+/// Boxed because rescheduling sets up the job that starts it again.
+pub fn watch_for_start(
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    livestream_url: String,
+) -> AsyncFn {
+    Box::pin(watch(livestream_scheduler, livestream_url))
+}
+
+async fn watch(livestream_scheduler: Arc<Mutex<LivestreamScheduler>>, livestream_url: String) {
+    let check_interval = Duration::from_secs(env_or("LIVE_CHECK_INTERVAL_SECS", 60));
+    let late_after = env_parse("LIVE_LATE_MINUTES").map(chrono::Duration::minutes);
+    let timeout = chrono::Duration::seconds(env_or("LIVE_CHECK_TIMEOUT_SECS", 3 * 60 * 60));
+
+    let Some(video_id) = crate::video_id(&livestream_url) else {
+        println!(
+            "No video id in {}, not watching for its start",
+            livestream_url
+        );
+        return;
+    };
+    let mongo = data::Mongo::new().await;
+    let client = youtube::YoutubeClient::new(mongo.clone());
+    let mut sent_late_message = false;
+    // The start time this watcher was started for.
+    let mut first_scheduled_dt = None;
+
+    loop {
+        let livestream = match mongo.get_livestream(&livestream_url).await {
+            Ok(Some(livestream)) if livestream.deleted_at.is_none() => livestream,
+            Ok(_) => {
+                println!(
+                    "Livestream was removed, not watching for its start ({})",
+                    livestream_url
+                );
+                return;
+            }
+            Err(e) => {
+                println!("Error loading livestream {}: {}", livestream_url, e);
+                tokio::time::sleep(check_interval).await;
+                continue;
+            }
+        };
+        let scheduled_dt = livestream.date.to_chrono();
+        let watched_dt = *first_scheduled_dt.get_or_insert(scheduled_dt);
+        if scheduled_dt != watched_dt && scheduled_dt > Utc::now() {
+            // The job set up for the new start time watches it from then on.
+            println!(
+                "Livestream moved to {}, not watching for its start until then ({})",
+                scheduled_dt, livestream_url
+            );
+            return;
+        }
+        let late_by = Utc::now() - scheduled_dt;
+
+        if late_by > timeout {
+            let message = format!(
+                "Livestream did not start within {} minutes of its scheduled time, no longer watching: {}",
+                timeout.num_minutes(),
+                livestream_url
+            );
+            println!("{}", message);
+            tokio::spawn(crate::send_message_to_developer(message));
+            return;
+        }
+
+        match client.get_video_metadata(video_id).await {
+            Ok(metadata) if metadata.actual_start_time.is_some() => {
+                println!(
+                    "Livestream started at {:?} ({})",
+                    metadata.actual_start_time, livestream_url
+                );
+                if let Err(e) = crate::send_is_live_message(&livestream).await {
+                    println!("Error sending live message for {}: {}", livestream_url, e);
+                }
+                return;
+            }
+            Ok(metadata) => match metadata.livestream_start_dt {
+                None => {
+                    println!("No longer a livestream ({})", livestream_url);
+                    return;
+                }
+                Some(stream_dt) if stream_dt > Utc::now() && stream_dt != scheduled_dt => {
+                    // Rescheduling sets up new notifications, which watch again at the new time.
+                    if let Err(e) =
+                        crate::refresh::reschedule(livestream_scheduler, livestream, metadata).await
+                    {
+                        println!("Error rescheduling livestream {}: {}", livestream_url, e);
+                    }
+                    return;
+                }
+                Some(_) => {}
+            },
+            Err(youtube::YoutubeError::NotFound(_)) => {
+                println!(
+                    "Livestream is gone, not watching for its start ({})",
+                    livestream_url
+                );
+                return;
+            }
+            Err(
+                e
+                @ (youtube::YoutubeError::QuotaExceeded(_) | youtube::YoutubeError::MissingApiKey),
+            ) => {
+                println!(
+                    "Cannot check whether {} started ({}), announcing it as live",
+                    livestream_url, e
+                );
+                if let Err(e) = crate::send_is_live_message(&livestream).await {
+                    println!("Error sending live message for {}: {}", livestream_url, e);
+                }
+                return;
+            }
+            Err(e) => println!("Error checking whether {} started: {}", livestream_url, e),
+        }
+
+        if let Some(late_after) = late_after {
+            if !sent_late_message && late_by >= late_after {
+                if let Err(e) = crate::send_livestream_late_message(&livestream, late_by).await {
+                    println!("Error sending late message for {}: {}", livestream_url, e);
+                }
+                sent_late_message = true;
+            }
+        }
+
+        tokio::time::sleep(check_interval).await;
+    }
+}
@@ -1,24 +1,33 @@
+mod archive;
 mod commands;
 mod cron;
 mod data;
+mod dedupe;
 mod discord;
+mod live;
+mod poll;
+mod queue;
+mod reconcile;
+mod refresh;
+mod silence;
 mod youtube;
+use hololive_livestream_notifier_rs::config::{env_or, env_parse};
 use hololive_livestream_notifier_rs::pubsub;
 
 use axum::{
-    body::Body,
-    extract::Query,
+    body::{Body, Bytes},
     http::Request,
-    http::StatusCode,
-    routing::{get, post},
-    Extension, Router,
+    http::{HeaderMap, StatusCode},
+    routing::get,
+    Router,
 };
-use chrono::{DateTime, Datelike, FixedOffset, NaiveDateTime, Timelike, Utc};
+use chrono::{DateTime, Datelike, FixedOffset, Timelike, Utc};
 use cron::LivestreamScheduler;
 use dotenv::dotenv;
+use futures::StreamExt;
 use poise::serenity_prelude::{self as serenity};
 use quick_xml::de::from_str;
-use serde::Deserialize;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::{env::var, time::Duration};
@@ -31,7 +40,10 @@ type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
 
 // Custom data passed to all command functions
-pub struct Data {}
+pub struct Data {
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    deduplicator: Arc<dedupe::Deduplicator>,
+}
 
 // TODO LIST
 // TODO: Add logging package
@@ -39,18 +51,6 @@ pub struct Data {}
 // TODO: Add tracing
 // TODO: Reorganize code
 
-#[derive(Debug, Deserialize)]
-pub struct YTCallbackParams {
-    #[serde(rename = "hub.challenge")]
-    challenge: Option<String>,
-    #[serde(rename = "hub.mode")]
-    mode: Option<String>,
-    #[serde(rename = "hub.topic")]
-    topic: Option<String>,
-    #[serde(rename = "hub.lease_seconds")]
-    lease_seconds: Option<i64>,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
@@ -69,18 +69,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // abort();
     // --- End of temp code
 
-    tokio::spawn(start_bot());
     let livestream_scheduler = Arc::new(Mutex::new(LivestreamScheduler::new().await));
+    let deduplicator = Arc::new(dedupe::Deduplicator::new());
+    deduplicator.create_expiry_index().await;
+    tokio::spawn(start_bot(
+        Arc::clone(&livestream_scheduler),
+        Arc::clone(&deduplicator),
+    ));
 
     // tracing_subscriber::fmt::init();
     setup_existing_livestream_notifications(Arc::clone(&livestream_scheduler)).await;
-    tokio::spawn(subscribe_to_feeds());
+    tokio::spawn(refresh::refresh_upcoming(Arc::clone(&livestream_scheduler)));
+
+    let mut app = Router::new().route("/", get(default_handler));
+
+    if let Some(pubsub_callback_url) = std::env::var("PUBSUB_CALLBACK_URL")
+        .ok()
+        .filter(|url| !url.trim().is_empty())
+    {
+        let mut pubsub = pubsub::PubSub::builder(reqwest::Url::parse(&pubsub_callback_url)?);
+        if let Some(fallback_hub) = std::env::var("PUBSUB_FALLBACK_HUB")
+            .ok()
+            .filter(|hub| !hub.trim().is_empty())
+        {
+            pubsub = pubsub.fallback_hub(fallback_hub);
+        }
+        if let Some(hub) = std::env::var("PUBSUB_HUB")
+            .ok()
+            .filter(|hub| !hub.trim().is_empty())
+        {
+            pubsub = pubsub.hub(hub);
+        }
+        if let Some(lease_seconds) = env_parse("PUBSUB_LEASE_SECONDS") {
+            pubsub = pubsub.lease_seconds(lease_seconds);
+        }
+        let pubsub = Arc::new(pubsub.build());
+        restore_subscriptions(&pubsub).await;
+        tokio::spawn(reconcile::reconcile_feeds(Arc::clone(&pubsub)));
+        tokio::spawn(reconcile::watch_feeds(Arc::clone(&pubsub)));
+        tokio::spawn(renew_subscriptions(Arc::clone(&pubsub)));
+        tokio::spawn(silence::watch_silence(
+            Arc::clone(&pubsub),
+            Arc::clone(&livestream_scheduler),
+            Arc::clone(&deduplicator),
+        ));
+
+        let delivery_queue = Arc::new(queue::DeliveryQueue::new());
+        let delivery_workers = env_or("DELIVERY_WORKERS", 2);
+        for _ in 0..delivery_workers {
+            tokio::spawn(
+                Arc::clone(&delivery_queue)
+                    .run_worker(Arc::clone(&livestream_scheduler), Arc::clone(&deduplicator)),
+            );
+        }
+
+        // Served at the path of PUBSUB_CALLBACK_URL.
+        app = app.merge(pubsub.router(Arc::new(YoutubeDeliveries { delivery_queue })));
+    } else {
+        println!("PUBSUB_CALLBACK_URL is not set, polling feeds instead of subscribing");
+        tokio::spawn(poll::poll_feeds(
+            Arc::clone(&livestream_scheduler),
+            Arc::clone(&deduplicator),
+        ));
+    }
 
-    let app = Router::new()
-        .route("/", get(default_handler))
-        .route("/yt-pubsub", get(yt_pubsub_challenge_handler))
-        .route("/yt-pubsub", post(yt_pubsub_callback))
-        .layer(Extension(livestream_scheduler));
     let addr = SocketAddr::from(([0, 0, 0, 0], std::env::var("PORT")?.parse()?));
 
     // tracing::debug!("listening on {}", addr);
@@ -106,24 +158,57 @@ async fn on_error(error: poise::FrameworkError<'_, Data, Error>) {
     }
 }
 
-async fn subscribe_to_feeds() {
-    let feeds = data::Mongo::new().await.get_feeds().await.unwrap();
-    let pubsub_callback_url = std::env::var("PUBSUB_CALLBACK_URL").ok().unwrap();
-    let pubsub_callback_url = reqwest::Url::parse(&pubsub_callback_url).ok().unwrap();
-    let mut pubsub = pubsub::PubSub::new(pubsub_callback_url);
+/// Resubscribe to every topic whose lease is about to run out.
+///
+/// `PUBSUB_RENEWAL_MARGIN_SECS` controls how long before expiry a topic is renewed and
+/// `PUBSUB_RENEWAL_CHECK_INTERVAL_SECS` how often leases are checked.
+async fn renew_subscriptions(pubsub: Arc<pubsub::PubSub>) {
+    let margin = chrono::Duration::seconds(env_or("PUBSUB_RENEWAL_MARGIN_SECS", 24 * 60 * 60));
+    let check_interval = Duration::from_secs(env_or("PUBSUB_RENEWAL_CHECK_INTERVAL_SECS", 60 * 60));
 
-    for feed in feeds {
-        if let Err(e) = pubsub.subscribe(feed.topic_url.clone()).await {
-            println!("Error subscribing to {:?}: {:?}", feed.topic_url, e);
-            continue;
+    loop {
+        tokio::time::sleep(check_interval).await;
+
+        for subscription in pubsub.expire_lapsed().await {
+            println!("Subscription to {:?} expired", subscription.topic.as_str());
+            save_subscription(&subscription.topic, Some(&subscription)).await;
         }
 
-        println!(
-            "Sent subscription request for {:?} {:?} ({:?})",
-            feed.first_name,
-            feed.last_name,
-            feed.topic_url.as_str()
-        );
+        let expiring = pubsub.expiring_subscriptions(margin).await;
+        reconcile::subscribe_all(&pubsub, expiring, "renewals").await;
+    }
+}
+
+/// Load the subscriptions saved by a previous run into `pubsub`.
+async fn restore_subscriptions(pubsub: &pubsub::PubSub) {
+    let subscriptions = data::Mongo::new().await.get_subscriptions().await.unwrap();
+
+    let subscriptions = subscriptions
+        .into_iter()
+        .filter_map(|subscription| match subscription.try_into() {
+            Ok(subscription) => Some(subscription),
+            Err(e) => {
+                println!("Error restoring subscription: {}", e);
+                None
+            }
+        })
+        .collect();
+
+    pubsub.restore(subscriptions).await;
+}
+
+/// Mirror a subscription in the `subscriptions` collection, removing it when it is
+/// no longer tracked.
+async fn save_subscription(topic: &reqwest::Url, subscription: Option<&pubsub::Subscription>) {
+    let mongo = data::Mongo::new().await;
+
+    let result = match subscription {
+        Some(subscription) => mongo.upsert_subscription(&subscription.into()).await,
+        None => mongo.delete_subscription(topic.as_str()).await,
+    };
+
+    if let Err(e) = result {
+        println!("Error saving subscription for {:?}: {}", topic.as_str(), e);
     }
 }
 
@@ -140,11 +225,16 @@ async fn setup_existing_livestream_notifications(
     }
 }
 
-async fn start_bot() {
+async fn start_bot(
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    deduplicator: Arc<dedupe::Deduplicator>,
+) {
     let options = poise::FrameworkOptions {
         commands: vec![
             commands::help(),
             commands::ping(),
+            commands::replay(),
+            commands::quota(),
             // commands::vote(),
             // commands::getvotes(),
         ],
@@ -194,6 +284,8 @@ async fn start_bot() {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
                 Ok(Data {
                     // votes: Mutex::new(HashMap::new()),
+                    livestream_scheduler,
+                    deduplicator,
                 })
             })
         })
@@ -210,79 +302,227 @@ async fn default_handler(request: Request<Body>) -> StatusCode {
     StatusCode::OK
 }
 
-async fn yt_pubsub_challenge_handler(
-    Query(params): Query<YTCallbackParams>,
-) -> (StatusCode, String) {
-    println!(
-        "Received [{:?}] challenge for topic {:?}. Subscription lasts for [{:?}] seconds. Responding with challenge {:?}.",
-        params.mode, params.topic, params.lease_seconds, params.challenge
-    );
-
-    (StatusCode::OK, params.challenge.unwrap_or_default())
+/// Receives the deliveries for the feeds we subscribed to.
+///
+/// Every delivery is archived. Verified ones are queued for the [`queue::DeliveryQueue`]
+/// workers, so the hub gets an answer right away, whatever the outcome of processing.
+struct YoutubeDeliveries {
+    delivery_queue: Arc<queue::DeliveryQueue>,
 }
 
-async fn yt_pubsub_callback(
-    Extension(livestream_scheduler): Extension<Arc<Mutex<cron::LivestreamScheduler>>>,
-    payload: String,
-) -> StatusCode {
-    println!("Processing youtube feed: {}", payload);
-
-    let yt_feed = from_str::<pubsub::YoutubeFeed>(&payload).unwrap();
+impl pubsub::DeliveryHandler for YoutubeDeliveries {
+    async fn deliver(
+        &self,
+        delivery: pubsub::Delivery,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let payload = String::from_utf8_lossy(&delivery.body).to_string();
+        println!("Received youtube feed: {}", payload);
+        let archive_id = archive::archive_payload(&delivery.headers, &payload).await;
+
+        if let Err(e) = self
+            .delivery_queue
+            .enqueue(delivery.topic.as_str(), payload, archive_id)
+            .await
+        {
+            // Without a queued job the notification would be lost, so let the hub retry.
+            archive::record_outcome(archive_id, &format!("not queued: {}", e)).await;
+            return Err(format!(
+                "Error queueing delivery for {:?}: {}",
+                delivery.topic.as_str(),
+                e
+            )
+            .into());
+        }
 
-    println!("Parsed feed: {:?}", yt_feed);
+        archive::record_outcome(archive_id, "queued").await;
+        Ok(())
+    }
 
-    {
-        let yt_feed_json_str = serde_json::to_string_pretty(&yt_feed).unwrap().to_string();
-        let yt_feed_json_str = format!("Processing feed:\n```json\n{}\n```", yt_feed_json_str);
-        tokio::spawn(send_message_to_developer(yt_feed_json_str));
+    async fn rejected(&self, headers: &HeaderMap, body: &Bytes, rejection: &pubsub::Rejection) {
+        let payload = String::from_utf8_lossy(body);
+        let archive_id = archive::archive_payload(headers, &payload).await;
+        archive::record_outcome(archive_id, &format!("rejected: {}", rejection)).await;
     }
 
-    let livestream_url = yt_feed.entry.first().unwrap().link.href.as_str();
-
-    process_url(
-        livestream_scheduler,
-        livestream_url,
-        yt_feed
-            .entry
-            .first()
-            .unwrap()
-            .updated
-            .unwrap()
-            .timestamp_millis(),
-    )
-    .await
-    .unwrap();
+    async fn subscription_changed(
+        &self,
+        topic: &reqwest::Url,
+        subscription: Option<&pubsub::Subscription>,
+    ) {
+        save_subscription(topic, subscription).await;
+    }
 
-    StatusCode::OK
+    fn topic(&self, body: &[u8]) -> Option<reqwest::Url> {
+        from_str::<pubsub::youtube::YoutubeFeed>(&String::from_utf8_lossy(body))
+            .ok()
+            .and_then(|yt_feed| yt_feed.topic())
+    }
 }
 
-async fn process_url(
+/// Process every entry and tombstone in `yt_feed`, at most `PUBSUB_ENTRY_CONCURRENCY`
+/// at a time.
+///
+/// Entries are claimed first, so duplicates are dropped before any lookup. The videos of
+/// the remaining entries are then looked up together with `priority`. A failing
+/// notification does not stop the others. Returns a description of each failure.
+async fn process_feed(
     livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
-    livestream_url: &str,
-    updated_ts_ms: i64,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let video_id = if let Some(captures) = regex::Regex::new(r"v=([^&]+)")
-        .unwrap()
-        .captures(livestream_url)
-    {
-        captures.get(1).map_or("", |m| m.as_str())
+    deduplicator: Arc<dedupe::Deduplicator>,
+    yt_feed: pubsub::youtube::YoutubeFeed,
+    priority: youtube::Priority,
+) -> Vec<String> {
+    let concurrency = env_or("PUBSUB_ENTRY_CONCURRENCY", 4);
+
+    let mut failures = Vec::new();
+    let mut claimed = Vec::new();
+    let claims: Vec<_> = futures::stream::iter(yt_feed.into_notifications())
+        .map(|notification| claim_notification(&deduplicator, notification))
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    for claim in claims {
+        match claim {
+            Ok(Some(notification)) => claimed.push(notification),
+            Ok(None) => {}
+            Err(e) => failures.push(e),
+        }
+    }
+
+    let video_ids: Vec<_> = claimed
+        .iter()
+        .filter_map(|(notification, _)| match notification {
+            pubsub::youtube::YoutubeNotification::Entry(entry) => Some(entry.video_id.as_str()),
+            pubsub::youtube::YoutubeNotification::Deleted(_) => None,
+        })
+        .collect();
+    let mut metadata = if !video_ids.is_empty() {
+        youtube::YoutubeClient::new(data::Mongo::new().await)
+            .get_videos_metadata(&video_ids, priority)
+            .await
     } else {
-        ""
+        HashMap::new()
     };
 
-    let mongo = data::Mongo::new().await;
-    let livestream = mongo.get_livestream(livestream_url).await;
+    let processed: Vec<_> = futures::stream::iter(claimed)
+        .map(|(notification, dedupe_key)| {
+            let metadata = match &notification {
+                pubsub::youtube::YoutubeNotification::Entry(entry) => {
+                    metadata.remove(&entry.video_id)
+                }
+                pubsub::youtube::YoutubeNotification::Deleted(_) => None,
+            };
+            process_notification(
+                Arc::clone(&livestream_scheduler),
+                Arc::clone(&deduplicator),
+                notification,
+                dedupe_key,
+                metadata,
+            )
+        })
+        .buffer_unordered(concurrency)
+        .filter_map(|failure| async move { failure })
+        .collect()
+        .await;
+    failures.extend(processed);
 
-    if livestream.is_err() {
-        println!("Error getting livestream: {}", livestream.err().unwrap());
-        return Ok(());
+    failures
+}
+
+/// Claim `notification` so no other delivery of it is processed.
+///
+/// Returns the notification with the claimed dedupe key, `None` if it is a duplicate, or a
+/// description of the failure. Tombstones and entries without a timestamp are not claimed.
+async fn claim_notification(
+    deduplicator: &dedupe::Deduplicator,
+    notification: pubsub::youtube::YoutubeNotification,
+) -> Result<Option<(pubsub::youtube::YoutubeNotification, Option<String>)>, String> {
+    let pubsub::youtube::YoutubeNotification::Entry(entry) = &notification else {
+        return Ok(Some((notification, None)));
+    };
+    let Some(updated) = entry.updated.or(entry.published) else {
+        return Ok(Some((notification, None)));
+    };
+
+    let dedupe_key = dedupe::Deduplicator::key(&entry.video_id, updated.timestamp_millis());
+    match deduplicator.claim(&dedupe_key).await {
+        Ok(true) => Ok(Some((notification, Some(dedupe_key)))),
+        Ok(false) => Ok(None),
+        Err(e) => Err(format!("[{}] {}", entry.video_id, e)),
     }
+}
 
-    let livestream = livestream.unwrap();
-    let data = youtube::YoutubeClient::new()
-        .get_video_metadata(video_id)
-        .await
-        .unwrap();
+/// Process a single claimed notification, returning a description of the failure if it
+/// failed.
+///
+/// `dedupe_key` is released if processing fails, so a retry is not dropped. `metadata` is
+/// the already looked up video of an entry, if any.
+async fn process_notification(
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    deduplicator: Arc<dedupe::Deduplicator>,
+    notification: pubsub::youtube::YoutubeNotification,
+    dedupe_key: Option<String>,
+    metadata: Option<Result<youtube::VideoMetadata, youtube::YoutubeError>>,
+) -> Option<String> {
+    match notification {
+        pubsub::youtube::YoutubeNotification::Entry(entry) => {
+            let updated = entry.updated.or(entry.published);
+
+            let result = process_url(
+                livestream_scheduler,
+                entry.link.href.as_str(),
+                updated.unwrap_or_else(Utc::now).timestamp_millis(),
+                metadata,
+                false,
+            )
+            .await;
+
+            match result {
+                Ok(_) => {
+                    if let Some(dedupe_key) = &dedupe_key {
+                        deduplicator.complete(dedupe_key).await;
+                    }
+                    None
+                }
+                Err(e) => {
+                    if let Some(dedupe_key) = &dedupe_key {
+                        deduplicator.release(dedupe_key).await;
+                    }
+                    Some(format!("[{}] {}", entry.video_id, e))
+                }
+            }
+        }
+        pubsub::youtube::YoutubeNotification::Deleted(deleted_entry) => {
+            process_deleted_entry(livestream_scheduler, &deleted_entry, false)
+                .await
+                .err()
+                .map(|e| format!("[{}] {}", deleted_entry.reference, e))
+        }
+    }
+}
+
+/// Announce and schedule notifications for the livestream at `livestream_url`.
+///
+/// The video is looked up unless its `metadata` is given. With `dry_run` only the lookups
+/// are made. Returns a description of what was (or would have been) done.
+async fn process_url(
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    livestream_url: &str,
+    updated_ts_ms: i64,
+    metadata: Option<Result<youtube::VideoMetadata, youtube::YoutubeError>>,
+    dry_run: bool,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let video_id = video_id(livestream_url).unwrap_or("");
+
+    let mongo = data::Mongo::new().await;
+    let livestream = mongo.get_livestream(livestream_url).await?;
+    let data = match metadata {
+        Some(metadata) => metadata?,
+        None => {
+            youtube::YoutubeClient::new(mongo.clone())
+                .get_video_metadata(video_id)
+                .await?
+        }
+    };
 
     // if stream_dt.is_err() {
     //     tokio::spawn(send_message_to_developer(format!(
@@ -295,16 +535,33 @@ async fn process_url(
 
     // let stream_dt = stream_dt.unwrap();
 
-    let stream_dt = data.livestream_start_dt;
+    let Some(stream_dt) = data.livestream_start_dt else {
+        println!("Not a livestream ({})", livestream_url);
+        return Ok("Not a livestream".to_string());
+    };
 
     if stream_dt < Utc::now() {
         println!("Stream already started ({})", livestream_url);
-        return Ok(());
+        return Ok("Stream already started".to_string());
     }
 
     let stream_ts_ms = stream_dt.timestamp_millis();
     println!("Stream start datetime: {:?}", stream_dt);
 
+    let is_rescheduled = livestream
+        .as_ref()
+        .map(|livestream| livestream.date.timestamp_millis() != stream_ts_ms);
+    let outcome = match is_rescheduled {
+        None => format!("Announce stream starting at {}", stream_dt),
+        Some(true) => format!("Announce stream rescheduled to {}", stream_dt),
+        Some(false) => format!("Stream start unchanged ({})", stream_dt),
+    };
+
+    if dry_run {
+        println!("Dry run, not processing livestream ({})", livestream_url);
+        return Ok(outcome);
+    }
+
     match livestream {
         Some(mut livestream) => {
             let current_stream_ts_ms = livestream.date.timestamp_millis();
@@ -315,26 +572,26 @@ async fn process_url(
                 livestream.date = mongodb::bson::DateTime::from_millis(stream_ts_ms);
                 livestream.updated = mongodb::bson::DateTime::from_millis(updated_ts_ms);
 
-                mongo.upsert_livestream(&livestream).await.unwrap();
-                send_will_livestream_message(&livestream).await.unwrap();
+                let announcements = send_will_livestream_message(&livestream).await?;
+                livestream.announcements.extend(announcements);
+                mongo.upsert_livestream(&livestream).await?;
                 setup_livestream_notifications(Arc::clone(&livestream_scheduler), livestream)
-                    .await
-                    .unwrap();
+                    .await?;
             }
         }
         None => {
-            let livestream = data::models::Livestream {
+            let mut livestream = data::models::Livestream {
                 title: data.title,
                 author: data.channel_title,
                 url: livestream_url.to_string(),
                 date: mongodb::bson::DateTime::from_millis(stream_ts_ms),
                 updated: mongodb::bson::DateTime::from_millis(updated_ts_ms),
+                announcements: Vec::new(),
+                deleted_at: None,
             };
-            mongo.insert_livestream(&livestream).await.unwrap();
-            send_will_livestream_message(&livestream).await.unwrap();
-            setup_livestream_notifications(Arc::clone(&livestream_scheduler), livestream)
-                .await
-                .unwrap();
+            livestream.announcements = send_will_livestream_message(&livestream).await?;
+            mongo.insert_livestream(&livestream).await?;
+            setup_livestream_notifications(Arc::clone(&livestream_scheduler), livestream).await?;
         }
     }
 
@@ -343,18 +600,90 @@ async fn process_url(
         livestream_url
     )));
 
-    Ok(())
+    Ok(outcome)
+}
+
+/// The id of the video at `livestream_url`, e.g. `https://www.youtube.com/watch?v=<id>`.
+pub fn video_id(livestream_url: &str) -> Option<&str> {
+    regex::Regex::new(r"v=([^&]+)")
+        .unwrap()
+        .captures(livestream_url)
+        .and_then(|captures| captures.get(1))
+        .map(|m| m.as_str())
+}
+
+/// Handle a tombstone: stop the notifications of the deleted video's livestream and tell
+/// the channels that were told about the stream that it is cancelled.
+///
+/// The livestream is looked up by video id, since the tombstone's link is not always
+/// written like the entry's. With `dry_run` nothing is changed. Returns a description of
+/// what was (or would have been) done.
+async fn process_deleted_entry(
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    deleted_entry: &pubsub::youtube::YoutubeDeletedEntry,
+    dry_run: bool,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(deleted_video_id) = deleted_entry
+        .video_id()
+        .or_else(|| video_id(&deleted_entry.link.href))
+    else {
+        return Err(format!("No video id in tombstone {}", deleted_entry.reference).into());
+    };
+    let deleted = deleted_entry.when.unwrap_or_else(Utc::now);
+
+    let mongo = data::Mongo::new().await;
+
+    let Some(mut livestream) = mongo.get_livestream_by_video_id(deleted_video_id).await? else {
+        println!("Deleted video was never announced ({})", deleted_video_id);
+        return Ok("Deleted video was never announced".to_string());
+    };
+    let livestream_url = livestream.url.clone();
+
+    if livestream.deleted_at.is_some() {
+        println!("Livestream already marked as deleted ({})", livestream_url);
+        return Ok("Livestream already marked as deleted".to_string());
+    }
+
+    // A stream that already took place (e.g. a deleted archive) was not cancelled.
+    let is_past = livestream.date.to_chrono() <= Utc::now();
+    let outcome = if is_past {
+        "Mark past livestream as deleted".to_string()
+    } else {
+        format!(
+            "Cancel livestream in {} channel(s)",
+            livestream.announcements.len()
+        )
+    };
+
+    if dry_run {
+        println!("Dry run, not cancelling livestream ({})", livestream_url);
+        return Ok(outcome);
+    }
+
+    cancel_livestream_notifications(livestream_scheduler, &livestream.url).await;
+
+    // Sent before the tombstone is recorded, so a failed notice is sent again on retry.
+    if !is_past {
+        send_livestream_cancelled_message(&livestream).await?;
+    }
+
+    livestream.deleted_at = Some(mongodb::bson::DateTime::from_chrono(deleted));
+    mongo.upsert_livestream(&livestream).await?;
+
+    tokio::spawn(send_message_to_developer(format!(
+        "Processed deleted livestream: {}",
+        livestream_url
+    )));
+
+    Ok(outcome)
 }
 
 pub async fn setup_livestream_notifications(
     livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
     livestream: data::models::Livestream,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let timestamp = livestream.date.timestamp_millis() / 1000;
-    let date = DateTime::<Utc>::from_utc(
-        NaiveDateTime::from_timestamp_opt(timestamp, 0).unwrap(),
-        Utc,
-    );
+    let date = DateTime::<Utc>::from_timestamp(timestamp, 0).unwrap();
 
     let cron_schedule_str = format!(
         "{} {} {} {} {} *",
@@ -367,6 +696,7 @@ pub async fn setup_livestream_notifications(
 
     let stream_url = livestream.url.clone();
     let livestream2 = livestream.clone();
+    let watch_scheduler = Arc::clone(&livestream_scheduler);
     livestream_scheduler
         .lock()
         .await
@@ -374,14 +704,15 @@ pub async fn setup_livestream_notifications(
             stream_url.as_str(),
             &cron_schedule_str,
             Box::new(move |_job_uuid, _scheduler| {
-                let livestream = livestream.clone();
+                // Announced once it actually starts, which can be well after the scheduled time.
+                let watch =
+                    live::watch_for_start(Arc::clone(&watch_scheduler), livestream.url.clone());
                 Box::pin(async move {
-                    send_is_live_message(&livestream).await.unwrap();
+                    tokio::spawn(watch);
                 })
             }),
         )
-        .await
-        .unwrap();
+        .await?;
 
     let date = date - chrono::Duration::minutes(15);
     let cron_reminder_schedule_str = format!(
@@ -405,36 +736,132 @@ pub async fn setup_livestream_notifications(
                 })
             }),
         )
-        .await
-        .unwrap();
+        .await?;
 
     Ok(())
 }
 
+/// Remove the jobs created by [`setup_livestream_notifications`].
+pub async fn cancel_livestream_notifications(
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    stream_url: &str,
+) {
+    let mut livestream_scheduler = livestream_scheduler.lock().await;
+
+    livestream_scheduler
+        .cancel_livestream_notification(stream_url)
+        .await;
+    livestream_scheduler
+        .cancel_livestream_notification(format!("{}-reminder", stream_url).as_str())
+        .await;
+}
+
+fn will_livestream_message(livestream: &data::models::Livestream) -> String {
+    let timestamp = livestream.date.timestamp_millis() / 1000;
+    let mst_dt = DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .unwrap()
+        .with_timezone(&FixedOffset::west_opt(7 * 3600).unwrap());
+
+    format!(
+        "[{}] will livestream on [{}] - [{}]",
+        livestream.author,
+        mst_dt.format("%a, %b %e, %l:%M %p UTC%z"),
+        livestream.url
+    )
+}
+
 pub async fn send_will_livestream_message(
     livestream: &data::models::Livestream,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let timestamp = livestream.date.timestamp_millis() / 1000;
-    let mst_dt = DateTime::<FixedOffset>::from_utc(
-        NaiveDateTime::from_timestamp_opt(timestamp, 0).unwrap(),
-        FixedOffset::west_opt(7 * 3600).unwrap(),
+) -> Result<Vec<data::models::Announcement>, Box<dyn std::error::Error + Send + Sync>> {
+    let message = will_livestream_message(livestream);
+
+    let messages = discord::send_message_to_channel("hololive-notifications", &message).await?;
+
+    Ok(messages
+        .into_iter()
+        .map(|message| data::models::Announcement {
+            channel_id: message.channel_id.0,
+            message_id: message.id.0,
+        })
+        .collect())
+}
+
+/// Update every announcement of the stream with its current start time.
+///
+/// A failed edit (e.g. of a message that was deleted) does not stop the others. Returns
+/// the number of announcements that could not be edited.
+pub async fn edit_will_livestream_messages(livestream: &data::models::Livestream) -> usize {
+    let message = will_livestream_message(livestream);
+
+    let mut failures = 0;
+    for announcement in &livestream.announcements {
+        if let Err(e) =
+            discord::edit_message(announcement.channel_id, announcement.message_id, &message).await
+        {
+            println!(
+                "Error editing announcement {} in channel {}: {}",
+                announcement.message_id, announcement.channel_id, e
+            );
+            failures += 1;
+        }
+    }
+
+    failures
+}
+
+/// Tell the channels that were told about the stream that it is cancelled.
+pub async fn send_livestream_cancelled_message(
+    livestream: &data::models::Livestream,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let message = format!(
+        "[{}] Livestream cancelled - [{}]",
+        livestream.author, livestream.url
     );
 
+    reply_to_announcements(livestream, &message).await
+}
+
+/// Tell the channels that were told about the stream that it has not started yet.
+pub async fn send_livestream_late_message(
+    livestream: &data::models::Livestream,
+    late_by: chrono::Duration,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let message = format!(
-        "[{}] will livestream on [{}] - [{}]",
+        "[{}] Livestream is running late, it was scheduled {} minutes ago - [{}]",
         livestream.author,
-        mst_dt.format("%a, %b %e, %l:%M %p UTC%z"),
+        late_by.num_minutes(),
         livestream.url
     );
 
-    discord::send_message_to_channel("hololive-notifications", &message).await?;
+    reply_to_announcements(livestream, &message).await
+}
+
+/// Reply to the latest announcement in each channel that announced the stream.
+async fn reply_to_announcements(
+    livestream: &data::models::Livestream,
+    message: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Streams announced before announcements were recorded went to the default channel.
+    if livestream.announcements.is_empty() {
+        discord::send_message_to_channel("hololive-notifications", message).await?;
+        return Ok(());
+    }
+
+    let mut latest_announcements = HashMap::new();
+    for announcement in &livestream.announcements {
+        latest_announcements.insert(announcement.channel_id, announcement.message_id);
+    }
+
+    for (channel_id, message_id) in latest_announcements {
+        discord::reply_to_message(channel_id, message_id, message).await?;
+    }
 
     Ok(())
 }
 
 pub async fn send_livestream_reminder(
     livestream: &data::models::Livestream,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let message = format!(
         "[{}] Livestream starting in 15 minutes! - [{}]",
         livestream.author, livestream.url
@@ -447,7 +874,7 @@ pub async fn send_livestream_reminder(
 
 pub async fn send_is_live_message(
     livestream: &data::models::Livestream,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let message = format!(
         "[{}] Livestream starting! {}",
         livestream.author, livestream.url
@@ -0,0 +1,155 @@
+use crate::cron::LivestreamScheduler;
+use crate::data;
+use crate::dedupe::Deduplicator;
+use hololive_livestream_notifier_rs::config::env_or;
+use hololive_livestream_notifier_rs::pubsub;
+use quick_xml::de::from_str;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{StatusCode, Url};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// What we know about a feed from its previous fetch.
+#[derive(Default)]
+struct FeedState {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Dedupe keys of the entries in the last successfully processed fetch.
+    seen: Option<HashSet<String>>,
+}
+
+/// Used instead of WebSub when there is no public callback URL (e.g. running locally).
+///
+/// Fetches every feed in the `feeds` collection each `FEED_POLL_INTERVAL_SECS` and sends
+/// new or updated entries through the same pipeline as pushed deliveries. On the first
+/// fetch of a feed only entries updated within `FEED_POLL_BACKFILL_SECS` are processed.
+pub async fn poll_feeds(
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    deduplicator: Arc<Deduplicator>,
+) {
+    let interval = Duration::from_secs(env_or("FEED_POLL_INTERVAL_SECS", 5 * 60));
+    let backfill = chrono::Duration::seconds(env_or("FEED_POLL_BACKFILL_SECS", 60 * 60));
+
+    let client = reqwest::Client::new();
+    let mut states: HashMap<Url, FeedState> = HashMap::new();
+
+    loop {
+        match data::Mongo::new().await.get_feeds().await {
+            Ok(feeds) => {
+                let topics: HashSet<_> = feeds.into_iter().map(|feed| feed.topic_url).collect();
+                states.retain(|topic, _| topics.contains(topic));
+
+                for topic in topics {
+                    let state = states.entry(topic.clone()).or_default();
+                    if let Err(e) = poll_feed(
+                        &client,
+                        &topic,
+                        state,
+                        backfill,
+                        Arc::clone(&livestream_scheduler),
+                        Arc::clone(&deduplicator),
+                    )
+                    .await
+                    {
+                        println!("Error polling {:?}: {}", topic.as_str(), e);
+                    }
+                }
+            }
+            Err(e) => println!("Error getting feeds to poll: {}", e),
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn poll_feed(
+    client: &reqwest::Client,
+    topic: &Url,
+    state: &mut FeedState,
+    backfill: chrono::Duration,
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    deduplicator: Arc<Deduplicator>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut request = client.get(topic.clone());
+    if let Some(etag) = &state.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &state.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(());
+    }
+    let response = response.error_for_status()?;
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+
+    let mut yt_feed = from_str::<pubsub::youtube::YoutubeFeed>(&response.text().await?)?;
+
+    let entry_key = |entry: &pubsub::youtube::YoutubeEntry| {
+        let updated = entry.updated.or(entry.published).unwrap_or_default();
+        Deduplicator::key(&entry.video_id, updated.timestamp_millis())
+    };
+    let keys: HashSet<_> = yt_feed.entry.iter().map(entry_key).collect();
+
+    let backfill_since = chrono::Utc::now() - backfill;
+    yt_feed.entry.retain(|entry| match &state.seen {
+        Some(seen) => !seen.contains(&entry_key(entry)),
+        None => entry
+            .updated
+            .or(entry.published)
+            .is_some_and(|updated| updated > backfill_since),
+    });
+
+    if !yt_feed.entry.is_empty() {
+        println!(
+            "Polled {} new or updated entries from {:?}",
+            yt_feed.entry.len(),
+            topic.as_str()
+        );
+
+        let failures = crate::process_feed(
+            livestream_scheduler,
+            deduplicator,
+            yt_feed,
+            crate::youtube::Priority::High,
+        )
+        .await;
+        if !failures.is_empty() {
+            // Fetch the whole feed again next time so the failed entries are retried.
+            state.etag = None;
+            state.last_modified = None;
+            return Err(failures.join("\n").into());
+        }
+    }
+
+    state.etag = etag;
+    state.last_modified = last_modified;
+    state.seen = Some(keys);
+
+    Ok(())
+}
+
+/// Fetch and parse the current contents of a feed.
+pub async fn fetch_feed(
+    client: &reqwest::Client,
+    topic: &Url,
+) -> Result<pubsub::youtube::YoutubeFeed, Box<dyn std::error::Error + Send + Sync>> {
+    let response = client.get(topic.clone()).send().await?.error_for_status()?;
+
+    Ok(from_str::<pubsub::youtube::YoutubeFeed>(
+        &response.text().await?,
+    )?)
+}
@@ -1,148 +1,435 @@
-use chrono::DateTime;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::{HeaderMap, StatusCode},
+    routing::get,
+    Extension, Router,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
 use quick_xml::de::from_str;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Mutex;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct YoutubeLink {
-    #[serde(rename = "@rel")]
-    pub rel: String,
-    #[serde(rename = "@href")]
-    pub href: String,
+pub mod youtube;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Subscribe,
+    Unsubscribe,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct YoutubeAuthor {
-    pub name: String,
-    pub uri: String,
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Subscribe => "subscribe",
+            Mode::Unsubscribe => "unsubscribe",
+        }
+    }
+
+    /// Parse the `hub.mode` of a verification request.
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "subscribe" => Some(Mode::Subscribe),
+            "unsubscribe" => Some(Mode::Unsubscribe),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct YoutubeEntry {
-    pub id: String,
-    #[serde(rename = "videoId")]
-    pub video_id: String,
-    #[serde(rename = "channelId")]
-    pub channel_id: String,
-    pub title: String,
-    pub link: YoutubeLink,
-    pub author: YoutubeAuthor,
-    #[serde(deserialize_with = "de_time", serialize_with = "se_time", default)]
-    pub published: Option<DateTime<chrono::Utc>>,
-    #[serde(deserialize_with = "de_time", serialize_with = "se_time", default)]
-    pub updated: Option<DateTime<chrono::Utc>>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct YoutubeFeed {
-    pub title: String,
-    #[serde(default)]
-    pub link: Vec<YoutubeLink>,
-    #[serde(deserialize_with = "de_time", serialize_with = "se_time", default)]
-    pub updated: Option<DateTime<chrono::Utc>>,
-    #[serde(default)]
-    pub entry: Vec<YoutubeEntry>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriptionState {
+    /// Requested from the hub but not verified yet.
+    Pending,
+    /// The hub verified the subscription and is delivering.
+    Verified,
+    /// The hub refused or revoked the subscription.
+    Denied,
+    /// The lease ran out before it was renewed.
+    Expired,
 }
 
-/// Deserialize a `DateTime` from an RFC 3339 date string.
-fn de_time<'de, D>(deserializer: D) -> Result<Option<DateTime<chrono::Utc>>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s: String = Deserialize::deserialize(deserializer)?;
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    /// Identifies the subscription in its callback URL, see [`PubSub::callback_url`].
+    pub id: String,
+    pub hub: String,
+    pub topic: reqwest::Url,
+    /// Sent as `hub.secret`; the hub signs every delivery for this topic with it.
+    pub secret: String,
+    pub state: SubscriptionState,
+    /// The request we sent to the hub and are waiting to be asked to confirm.
+    pub intent: Option<Mode>,
+    /// When the last subscribe or unsubscribe request was sent.
+    pub requested_at: Option<DateTime<Utc>>,
+    /// When the hub will drop the subscription, as reported by the verified challenge.
+    pub lease_expires_at: Option<DateTime<Utc>>,
+    pub last_challenge_at: Option<DateTime<Utc>>,
+    pub last_delivery_at: Option<DateTime<Utc>>,
+}
 
-    let dt: Result<DateTime<chrono::FixedOffset>, chrono::ParseError> =
-        chrono::DateTime::parse_from_rfc3339(&s);
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The delivery had no `X-Hub-Signature` header.
+    Missing,
+    /// The header was not of the form `method=signature` with a supported method.
+    Malformed(String),
+    /// The delivery is for a topic we hold no secret for.
+    UnknownTopic(String),
+    /// The signature does not match the body.
+    Mismatch,
+}
 
-    match dt {
-        Ok(dt) => {
-            let dt = dt.with_timezone(&chrono::Utc);
-            Ok(Some(dt))
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "Missing X-Hub-Signature header"),
+            SignatureError::Malformed(signature) => {
+                write!(f, "Malformed X-Hub-Signature header: {}", signature)
+            }
+            SignatureError::UnknownTopic(topic) => {
+                write!(f, "No subscription secret for topic {}", topic)
+            }
+            SignatureError::Mismatch => write!(f, "X-Hub-Signature does not match the body"),
         }
-        Err(e) => {
-            println!("Error parsing time ({}):  {}", s, e);
-            Ok(None)
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Number of deliveries rejected by [`PubSub::verify_signature`].
+#[derive(Debug, Clone, Copy)]
+pub struct RejectedDeliveries {
+    pub missing_signature: u64,
+    pub invalid_signature: u64,
+}
+
+/// Where to subscribe to a topic, as advertised by the topic itself.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    pub hub: String,
+    /// The canonical topic URL (`rel="self"`), which the hub expects in `hub.topic`.
+    pub topic: reqwest::Url,
+}
+
+/// A delivery whose signature checked out.
+#[derive(Debug)]
+pub struct Delivery {
+    /// The topic we subscribed with, even if the hub named its canonical URL.
+    pub topic: reqwest::Url,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// Why a delivery was not passed to [`DeliveryHandler::deliver`].
+#[derive(Debug)]
+pub enum Rejection {
+    /// The topic could not be told from the headers or the body.
+    MissingTopic,
+    /// The delivery was sent to the callback of a subscription we do not have, or the
+    /// hub named a different topic than the one of the subscription.
+    UnknownSubscription(String),
+    Signature(SignatureError),
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::MissingTopic => write!(f, "No topic for delivery"),
+            Rejection::UnknownSubscription(id) => {
+                write!(f, "No subscription {} for the delivered topic", id)
+            }
+            Rejection::Signature(e) => write!(f, "{}", e),
         }
     }
 }
 
-/// Serialize a `DateTime` into an RFC 3339 date string as bytes.
-fn se_time<S>(dt: &Option<DateTime<chrono::Utc>>, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    match dt {
-        Some(dt) => Ok(s.serialize_str(&dt.to_rfc3339())?),
-        None => Ok(s.serialize_str("")?),
+/// What to do with the deliveries received by [`PubSub::router`].
+pub trait DeliveryHandler: Send + Sync + 'static {
+    /// Handle a verified delivery. An error makes the callback answer 500 so the hub
+    /// delivers it again later.
+    fn deliver(
+        &self,
+        delivery: Delivery,
+    ) -> impl Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send;
+
+    /// Called for every delivery that was refused.
+    fn rejected(
+        &self,
+        _headers: &HeaderMap,
+        _body: &Bytes,
+        _rejection: &Rejection,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Called when the hub changed a subscription: a verification, a denial or a
+    /// delivery. `None` means the topic is no longer tracked. Use it to persist them.
+    fn subscription_changed(
+        &self,
+        _topic: &reqwest::Url,
+        _subscription: Option<&Subscription>,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// The topic of a delivery that has no `Link: <topic>; rel="self"` header.
+    ///
+    /// Defaults to the `<link rel="self">` of an Atom feed.
+    fn topic(&self, body: &[u8]) -> Option<reqwest::Url> {
+        let links = from_str::<FeedLinks>(&String::from_utf8_lossy(body)).ok()?;
+
+        links
+            .link
+            .iter()
+            .find(|link| link.rel == "self")
+            .and_then(|link| reqwest::Url::parse(&link.href).ok())
     }
 }
 
+/// The `<link>` elements of an Atom feed.
+#[derive(Debug, Deserialize)]
+struct FeedLinks {
+    #[serde(default)]
+    link: Vec<FeedLink>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedLink {
+    #[serde(rename = "@rel", default)]
+    rel: String,
+    #[serde(rename = "@href")]
+    href: String,
+}
+
+/// Query of a verification request from the hub.
+#[derive(Debug, Deserialize)]
+struct VerificationParams {
+    #[serde(rename = "hub.challenge")]
+    challenge: Option<String>,
+    #[serde(rename = "hub.mode")]
+    mode: Option<String>,
+    #[serde(rename = "hub.topic")]
+    topic: Option<String>,
+    #[serde(rename = "hub.lease_seconds")]
+    lease_seconds: Option<i64>,
+    #[serde(rename = "hub.reason")]
+    reason: Option<String>,
+}
+
+/// Builds a [`PubSub`], see [`PubSub::builder`].
 #[derive(Debug)]
-pub struct Subscription {
-    hub: String,
-    topic: reqwest::Url,
+pub struct PubSubBuilder {
+    callback_url: reqwest::Url,
+    secret: Option<String>,
+    lease_seconds: Option<i64>,
+    hub: Option<String>,
+    fallback_hub: Option<String>,
+}
+
+impl PubSubBuilder {
+    /// Use `secret` for every new subscription instead of generating one per topic.
+    pub fn secret(mut self, secret: String) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+
+    /// Ask hubs for leases of `lease_seconds`. Hubs pick their own default otherwise.
+    pub fn lease_seconds(mut self, lease_seconds: i64) -> Self {
+        self.lease_seconds = Some(lease_seconds);
+        self
+    }
+
+    /// Subscribe through `hub` for every topic, without discovering hubs.
+    pub fn hub(mut self, hub: String) -> Self {
+        self.hub = Some(hub);
+        self
+    }
+
+    /// Use `hub` for topics that do not advertise one.
+    pub fn fallback_hub(mut self, hub: String) -> Self {
+        self.fallback_hub = Some(hub);
+        self
+    }
+
+    pub fn build(self) -> PubSub {
+        PubSub {
+            client: reqwest::Client::new(),
+            subscriptions: Mutex::new(HashMap::new()),
+            discoveries: Mutex::new(HashMap::new()),
+            callback_url: self.callback_url,
+            secret: self.secret,
+            lease_seconds: self.lease_seconds,
+            hub: self.hub,
+            fallback_hub: self.fallback_hub,
+            missing_signature_count: AtomicU64::new(0),
+            invalid_signature_count: AtomicU64::new(0),
+        }
+    }
 }
 
+/// A WebSub subscriber.
+///
+/// Sends subscription requests to the hubs of the topics and keeps track of their state.
+/// [`PubSub::router`] answers the hubs' verification requests and deliveries.
 #[derive(Debug)]
 pub struct PubSub {
     client: reqwest::Client,
-    subscriptions: HashMap<reqwest::Url, Subscription>,
+    subscriptions: Mutex<HashMap<reqwest::Url, Subscription>>,
+    /// Discovered hubs, keyed by the topic URL we were asked to subscribe to.
+    discoveries: Mutex<HashMap<reqwest::Url, Discovery>>,
     callback_url: reqwest::Url,
+    secret: Option<String>,
+    lease_seconds: Option<i64>,
+    /// Hub used for every topic, instead of discovery.
+    hub: Option<String>,
+    /// Hub used when a topic does not advertise one.
+    fallback_hub: Option<String>,
+    missing_signature_count: AtomicU64,
+    invalid_signature_count: AtomicU64,
 }
 
 impl PubSub {
     pub fn new(callback_url: reqwest::Url) -> Self {
-        Self {
-            client: reqwest::Client::new(),
-            subscriptions: HashMap::new(),
+        Self::builder(callback_url).build()
+    }
+
+    /// Hubs will be asked to send verification requests and deliveries to `callback_url`.
+    pub fn builder(callback_url: reqwest::Url) -> PubSubBuilder {
+        PubSubBuilder {
             callback_url,
+            secret: None,
+            lease_seconds: None,
+            hub: None,
+            fallback_hub: None,
         }
     }
 
-    pub async fn subscribe(&mut self, url: reqwest::Url) -> Result<(), Box<dyn std::error::Error>> {
-        let hub = self.discover_hub(url.as_str()).await?;
+    /// Routes answering the hubs at the callback URL of every subscription.
+    ///
+    /// Verification requests are confirmed against the pending intents and deliveries are
+    /// checked against the subscription's topic and secret before being passed to
+    /// `handler`. The callback URL itself is still served for subscriptions made before
+    /// each had its own, and tells them apart by the topic the hub names.
+    pub fn router<H: DeliveryHandler>(self: Arc<Self>, handler: Arc<H>) -> Router {
+        let path = self.callback_url.path();
+        let subscription_path = format!("{}/:subscription_id", path.trim_end_matches('/'));
 
-        let mut form_data = HashMap::new();
-        form_data.insert("hub.callback", self.callback_url.as_str());
-        form_data.insert("hub.topic", url.as_str());
-        form_data.insert("hub.mode", "subscribe");
-
-        let response = self
-            .client
-            .post(&hub)
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .form(&form_data)
-            .send()
-            .await?;
+        Router::new()
+            .route(
+                path,
+                get(verification_handler::<H>).post(delivery_handler::<H>),
+            )
+            .route(
+                &subscription_path,
+                get(subscription_verification_handler::<H>)
+                    .post(subscription_delivery_handler::<H>),
+            )
+            .layer(Extension(handler))
+            .layer(Extension(self))
+    }
 
-        if response.status() != 202 {
-            return Err(format!("Status code not 202. {:?}", response).into());
+    /// Where the hub sends the verification requests and deliveries of a subscription:
+    /// `subscription_id` appended to the path of the callback URL.
+    pub fn callback_url(&self, subscription_id: &str) -> reqwest::Url {
+        let mut callback_url = self.callback_url.clone();
+        if let Ok(mut segments) = callback_url.path_segments_mut() {
+            segments.pop_if_empty().push(subscription_id);
         }
+        callback_url
+    }
 
-        let url_clone = url.clone();
+    /// Load subscriptions saved by a previous run.
+    pub async fn restore(&self, subscriptions: Vec<Subscription>) {
+        let mut current = self.subscriptions.lock().await;
 
-        self.subscriptions.insert(
-            url,
-            Subscription {
-                hub,
-                topic: url_clone,
-            },
-        );
+        for subscription in subscriptions {
+            current.insert(subscription.topic.clone(), subscription);
+        }
+    }
 
-        println!("Subscribe {:?}", response);
-        Ok(())
+    pub async fn subscriptions(&self) -> Vec<Subscription> {
+        self.subscriptions.lock().await.values().cloned().collect()
+    }
+
+    pub async fn subscribe(
+        &self,
+        url: reqwest::Url,
+    ) -> Result<Option<Subscription>, Box<dyn std::error::Error + Send + Sync>> {
+        self.request(Mode::Subscribe, url).await
     }
 
     pub async fn unsubscribe(
-        &mut self,
+        &self,
+        url: reqwest::Url,
+    ) -> Result<Option<Subscription>, Box<dyn std::error::Error + Send + Sync>> {
+        self.request(Mode::Unsubscribe, url).await
+    }
+
+    /// Send a subscribe or unsubscribe request to the topic's hub.
+    ///
+    /// The intent is recorded before the request is sent, since the hub may ask us to
+    /// confirm it before it responds. Returns the subscription as it stands afterwards,
+    /// or `None` if the topic is no longer tracked.
+    async fn request(
+        &self,
+        mode: Mode,
         url: reqwest::Url,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let hub = self.discover_hub(url.as_str()).await?;
+    ) -> Result<Option<Subscription>, Box<dyn std::error::Error + Send + Sync>> {
+        let Discovery { hub, topic } = self.discover_hub(&url).await?;
 
+        // Reuse the secret when renewing: the hub keeps signing with the old one
+        // until the new request is verified.
+        let (id, secret, previous_intent) = {
+            let mut subscriptions = self.subscriptions.lock().await;
+            let subscription = subscriptions
+                .entry(url.clone())
+                .or_insert_with(|| Subscription {
+                    id: uuid::Uuid::new_v4().simple().to_string(),
+                    hub: hub.clone(),
+                    topic: url.clone(),
+                    secret: self
+                        .secret
+                        .clone()
+                        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()),
+                    state: SubscriptionState::Pending,
+                    intent: None,
+                    requested_at: None,
+                    lease_expires_at: None,
+                    last_challenge_at: None,
+                    last_delivery_at: None,
+                });
+            subscription.hub = hub.clone();
+            subscription.requested_at = Some(Utc::now());
+            let previous_intent = subscription.intent.replace(mode);
+
+            (
+                subscription.id.clone(),
+                subscription.secret.clone(),
+                previous_intent,
+            )
+        };
+
+        let lease_seconds = self
+            .lease_seconds
+            .map(|lease_seconds| lease_seconds.to_string());
+        let callback_url = self.callback_url(&id);
         let mut form_data = HashMap::new();
-        form_data.insert("hub.callback", self.callback_url.as_str());
-        form_data.insert("hub.topic", url.as_str());
-        form_data.insert("hub.mode", "unsubscribe");
+        form_data.insert("hub.callback", callback_url.as_str());
+        form_data.insert("hub.topic", topic.as_str());
+        form_data.insert("hub.mode", mode.as_str());
+        if mode == Mode::Subscribe {
+            form_data.insert("hub.secret", secret.as_str());
+            if let Some(lease_seconds) = &lease_seconds {
+                form_data.insert("hub.lease_seconds", lease_seconds.as_str());
+            }
+        }
 
         let response = self
             .client
@@ -150,32 +437,757 @@ impl PubSub {
             .header("Content-Type", "application/x-www-form-urlencoded")
             .form(&form_data)
             .send()
-            .await?;
+            .await;
+
+        let response = match response {
+            Ok(response) if response.status() == 202 => response,
+            response => {
+                self.abandon_intent(&url, mode, previous_intent).await;
+                // The hub may have moved; look it up again next time.
+                self.discoveries.lock().await.remove(&url);
+
+                return match response {
+                    Ok(response) => Err(format!("Status code not 202. {:?}", response).into()),
+                    Err(e) => Err(e.into()),
+                };
+            }
+        };
 
-        if response.status() != 202 {
-            return Err(format!("Status code not 202. {:?}", response).into());
+        println!("{} {:?}", mode.as_str(), response);
+
+        // The hub may already have confirmed an unsubscribe and dropped the topic.
+        Ok(self.subscriptions.lock().await.get(&url).cloned())
+    }
+
+    /// Forget an intent the hub never accepted.
+    async fn abandon_intent(&self, url: &reqwest::Url, mode: Mode, previous_intent: Option<Mode>) {
+        let mut subscriptions = self.subscriptions.lock().await;
+
+        if let Some(subscription) = subscriptions.get_mut(url) {
+            if subscription.intent == Some(mode) {
+                subscription.intent = previous_intent;
+            }
+
+            // A topic we only just started tracking has nothing left to wait for.
+            if subscription.intent.is_none() && subscription.state == SubscriptionState::Pending {
+                subscriptions.remove(url);
+            }
+        }
+    }
+
+    /// Confirm a verification request from the hub.
+    ///
+    /// Only a request matching a pending intent for `topic` is confirmed. A confirmed
+    /// subscribe records the lease and marks the subscription verified, a confirmed
+    /// unsubscribe forgets the topic. Returns `None` if nothing matched.
+    pub async fn confirm_intent(
+        &self,
+        mode: Mode,
+        topic: &reqwest::Url,
+        lease_seconds: Option<i64>,
+    ) -> Option<Subscription> {
+        let topic = &self.resolve_topic(topic).await;
+        let mut subscriptions = self.subscriptions.lock().await;
+        let subscription = subscriptions.get_mut(topic)?;
+
+        if subscription.intent != Some(mode) {
+            return None;
         }
 
-        self.subscriptions.remove(&url);
+        subscription.intent = None;
+        subscription.last_challenge_at = Some(Utc::now());
 
-        println!("Unsubscribe {:?}", response);
-        Ok(())
+        match mode {
+            Mode::Subscribe => {
+                subscription.state = SubscriptionState::Verified;
+                subscription.lease_expires_at = lease_seconds
+                    .map(|lease_seconds| Utc::now() + chrono::Duration::seconds(lease_seconds));
+
+                Some(subscription.clone())
+            }
+            Mode::Unsubscribe => subscriptions.remove(topic),
+        }
     }
 
-    async fn discover_hub(&self, url: &str) -> Result<String, Box<dyn std::error::Error>> {
-        let url = reqwest::Url::parse(url)?;
-        let data = reqwest::get(url).await?.text().await?;
+    /// Record that the hub denied (or later revoked) the subscription to `topic`.
+    pub async fn deny(&self, topic: &reqwest::Url) -> Option<Subscription> {
+        let topic = &self.resolve_topic(topic).await;
+        let mut subscriptions = self.subscriptions.lock().await;
+        let subscription = subscriptions.get_mut(topic)?;
 
-        let feed: YoutubeFeed = from_str(&data)?;
+        subscription.state = SubscriptionState::Denied;
+        subscription.intent = None;
+        subscription.lease_expires_at = None;
+        subscription.last_challenge_at = Some(Utc::now());
 
-        println!("Discovered Feed {:?}", feed);
+        Some(subscription.clone())
+    }
 
-        for link in feed.link {
-            if link.rel == "hub" {
-                return Ok(link.href);
+    /// Record that a verified delivery for `topic` arrived.
+    pub async fn record_delivery(&self, topic: &reqwest::Url) -> Option<Subscription> {
+        let topic = &self.resolve_topic(topic).await;
+        let mut subscriptions = self.subscriptions.lock().await;
+        let subscription = subscriptions.get_mut(topic)?;
+
+        subscription.last_delivery_at = Some(Utc::now());
+
+        Some(subscription.clone())
+    }
+
+    /// Mark verified subscriptions whose lease has run out as expired.
+    pub async fn expire_lapsed(&self) -> Vec<Subscription> {
+        let now = Utc::now();
+
+        self.subscriptions
+            .lock()
+            .await
+            .values_mut()
+            .filter(|subscription| {
+                subscription.state == SubscriptionState::Verified
+                    && subscription
+                        .lease_expires_at
+                        .is_some_and(|lease_expires_at| lease_expires_at <= now)
+            })
+            .map(|subscription| {
+                subscription.state = SubscriptionState::Expired;
+                subscription.clone()
+            })
+            .collect()
+    }
+
+    /// Topics whose lease expires within `margin` from now, or has already expired.
+    pub async fn expiring_subscriptions(&self, margin: chrono::Duration) -> Vec<reqwest::Url> {
+        let renew_before = Utc::now() + margin;
+
+        self.subscriptions
+            .lock()
+            .await
+            .values()
+            .filter(|subscription| match subscription.state {
+                SubscriptionState::Verified => subscription
+                    .lease_expires_at
+                    .is_some_and(|lease_expires_at| lease_expires_at <= renew_before),
+                SubscriptionState::Expired => true,
+                SubscriptionState::Pending | SubscriptionState::Denied => false,
+            })
+            .map(|subscription| subscription.topic.clone())
+            .collect()
+    }
+
+    /// Check the `X-Hub-Signature` header of a delivery for `topic` against the raw body.
+    ///
+    /// Rejected deliveries are counted, see [`PubSub::rejected_deliveries`].
+    pub async fn verify_signature(
+        &self,
+        topic: &reqwest::Url,
+        signature: Option<&str>,
+        body: &[u8],
+    ) -> Result<(), SignatureError> {
+        let result = match signature {
+            Some(signature) => {
+                let resolved_topic = self.resolve_topic(topic).await;
+                let secret = self
+                    .subscriptions
+                    .lock()
+                    .await
+                    .get(&resolved_topic)
+                    .map(|subscription| subscription.secret.clone());
+
+                match secret {
+                    Some(secret) => check_signature(&secret, signature, body),
+                    None => Err(SignatureError::UnknownTopic(topic.to_string())),
+                }
             }
+            None => Err(SignatureError::Missing),
+        };
+
+        match result {
+            Err(SignatureError::Missing) => {
+                self.missing_signature_count.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.invalid_signature_count.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(()) => {}
         }
 
-        Err("No hub found".into())
+        result
+    }
+
+    pub fn rejected_deliveries(&self) -> RejectedDeliveries {
+        RejectedDeliveries {
+            missing_signature: self.missing_signature_count.load(Ordering::Relaxed),
+            invalid_signature: self.invalid_signature_count.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The topic of the subscription `id`, if the hub named that topic (or none at all).
+    async fn subscription_topic(
+        &self,
+        id: &str,
+        named_topic: Option<&reqwest::Url>,
+    ) -> Option<reqwest::Url> {
+        let resolved_topic = match named_topic {
+            Some(named_topic) => Some(self.resolve_topic(named_topic).await),
+            None => None,
+        };
+
+        self.subscriptions
+            .lock()
+            .await
+            .values()
+            .find(|subscription| subscription.id == id)
+            .filter(|subscription| {
+                resolved_topic
+                    .as_ref()
+                    .is_none_or(|resolved_topic| resolved_topic == &subscription.topic)
+            })
+            .map(|subscription| subscription.topic.clone())
+    }
+
+    /// Map a canonical topic named by the hub back to the URL we subscribed with.
+    async fn resolve_topic(&self, topic: &reqwest::Url) -> reqwest::Url {
+        self.discoveries
+            .lock()
+            .await
+            .iter()
+            .find(|(_, discovery)| &discovery.topic == topic)
+            .map_or_else(|| topic.clone(), |(url, _)| url.clone())
+    }
+
+    /// Find the hub for `url`, caching the result per topic.
+    ///
+    /// The `Link` headers of the topic are preferred over the `<link>` elements of the
+    /// feed. The fallback hub, if any, is used (but not cached) when neither names one.
+    /// A hub set with [`PubSubBuilder::hub`] is used without looking.
+    pub async fn discover_hub(
+        &self,
+        url: &reqwest::Url,
+    ) -> Result<Discovery, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(hub) = &self.hub {
+            return Ok(Discovery {
+                hub: hub.clone(),
+                topic: url.clone(),
+            });
+        }
+
+        if let Some(discovery) = self.discoveries.lock().await.get(url) {
+            return Ok(discovery.clone());
+        }
+
+        let discovered = match self.fetch_discovery(url).await {
+            Ok(Some(discovery)) => Ok(discovery),
+            Ok(None) => Err("No hub found".into()),
+            Err(e) => Err(e),
+        };
+
+        match (discovered, &self.fallback_hub) {
+            (Ok(discovery), _) => {
+                println!("Discovered hub {:?} for {:?}", discovery, url.as_str());
+                self.discoveries
+                    .lock()
+                    .await
+                    .insert(url.clone(), discovery.clone());
+                Ok(discovery)
+            }
+            (Err(e), Some(fallback_hub)) => {
+                println!(
+                    "Hub discovery failed for {:?} ({}), using {:?}",
+                    url.as_str(),
+                    e,
+                    fallback_hub
+                );
+                Ok(Discovery {
+                    hub: fallback_hub.clone(),
+                    topic: url.clone(),
+                })
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    async fn fetch_discovery(
+        &self,
+        url: &reqwest::Url,
+    ) -> Result<Option<Discovery>, Box<dyn std::error::Error + Send + Sync>> {
+        let response = self.client.get(url.clone()).send().await?;
+
+        let links: Vec<(String, String)> = response
+            .headers()
+            .get_all(reqwest::header::LINK)
+            .iter()
+            .filter_map(|link| link.to_str().ok())
+            .flat_map(parse_link_header)
+            .collect();
+
+        let mut hub = links
+            .iter()
+            .find(|(rel, _)| rel == "hub")
+            .map(|(_, hub)| hub.clone());
+        let mut topic = links
+            .iter()
+            .find(|(rel, _)| rel == "self")
+            .and_then(|(_, topic)| reqwest::Url::parse(topic).ok());
+
+        if hub.is_none() {
+            let data = response.text().await?;
+
+            if let Ok(feed) = from_str::<FeedLinks>(&data) {
+                hub = feed
+                    .link
+                    .iter()
+                    .find(|link| link.rel == "hub")
+                    .map(|link| link.href.clone());
+                topic = topic.or_else(|| {
+                    feed.link
+                        .iter()
+                        .find(|link| link.rel == "self")
+                        .and_then(|link| reqwest::Url::parse(&link.href).ok())
+                });
+            }
+        }
+
+        Ok(hub.map(|hub| Discovery {
+            hub,
+            topic: topic.unwrap_or_else(|| url.clone()),
+        }))
+    }
+}
+
+async fn verification_handler<H: DeliveryHandler>(
+    Extension(pubsub): Extension<Arc<PubSub>>,
+    Extension(handler): Extension<Arc<H>>,
+    Query(params): Query<VerificationParams>,
+) -> (StatusCode, String) {
+    verify(&pubsub, handler.as_ref(), None, params).await
+}
+
+async fn subscription_verification_handler<H: DeliveryHandler>(
+    Extension(pubsub): Extension<Arc<PubSub>>,
+    Extension(handler): Extension<Arc<H>>,
+    Path(subscription_id): Path<String>,
+    Query(params): Query<VerificationParams>,
+) -> (StatusCode, String) {
+    verify(&pubsub, handler.as_ref(), Some(&subscription_id), params).await
+}
+
+/// Answer a verification request, sent to the callback of `subscription_id` if given.
+async fn verify<H: DeliveryHandler>(
+    pubsub: &PubSub,
+    handler: &H,
+    subscription_id: Option<&str>,
+    params: VerificationParams,
+) -> (StatusCode, String) {
+    println!(
+        "Received [{:?}] challenge for topic {:?}. Subscription lasts for [{:?}] seconds.",
+        params.mode, params.topic, params.lease_seconds
+    );
+
+    let Some(topic) = params
+        .topic
+        .as_deref()
+        .and_then(|topic| reqwest::Url::parse(topic).ok())
+    else {
+        return (StatusCode::NOT_FOUND, String::new());
+    };
+
+    let topic = match subscription_id {
+        Some(subscription_id) => {
+            match pubsub
+                .subscription_topic(subscription_id, Some(&topic))
+                .await
+            {
+                Some(topic) => topic,
+                None => {
+                    println!(
+                        "No subscription {} for {:?}. Refusing challenge.",
+                        subscription_id,
+                        topic.as_str()
+                    );
+                    return (StatusCode::NOT_FOUND, String::new());
+                }
+            }
+        }
+        None => topic,
+    };
+
+    if params.mode.as_deref() == Some("denied") {
+        return match pubsub.deny(&topic).await {
+            Some(subscription) => {
+                println!(
+                    "Subscription to {:?} was denied: {:?}",
+                    topic.as_str(),
+                    params.reason
+                );
+                handler
+                    .subscription_changed(&subscription.topic, Some(&subscription))
+                    .await;
+                (StatusCode::OK, String::new())
+            }
+            None => (StatusCode::NOT_FOUND, String::new()),
+        };
+    }
+
+    let Some(mode) = params.mode.as_deref().and_then(Mode::parse) else {
+        return (StatusCode::NOT_FOUND, String::new());
+    };
+
+    match pubsub
+        .confirm_intent(mode, &topic, params.lease_seconds)
+        .await
+    {
+        Some(subscription) => {
+            println!(
+                "Confirmed [{}] for {:?}. Lease expires at {:?}. Responding with challenge {:?}.",
+                mode.as_str(),
+                topic.as_str(),
+                subscription.lease_expires_at,
+                params.challenge
+            );
+
+            match mode {
+                Mode::Subscribe => {
+                    handler
+                        .subscription_changed(&subscription.topic, Some(&subscription))
+                        .await
+                }
+                Mode::Unsubscribe => {
+                    handler
+                        .subscription_changed(&subscription.topic, None)
+                        .await
+                }
+            }
+
+            (StatusCode::OK, params.challenge.unwrap_or_default())
+        }
+        None => {
+            println!(
+                "No pending [{}] intent for {:?}. Refusing challenge.",
+                mode.as_str(),
+                topic.as_str()
+            );
+            (StatusCode::NOT_FOUND, String::new())
+        }
+    }
+}
+
+async fn delivery_handler<H: DeliveryHandler>(
+    Extension(pubsub): Extension<Arc<PubSub>>,
+    Extension(handler): Extension<Arc<H>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    // Hubs name the topic in a `Link: <topic>; rel="self"` header, fall back to the payload.
+    let topic = link_topic(&headers).or_else(|| handler.topic(&body));
+
+    let Some(topic) = topic else {
+        println!("Rejected delivery without a self link");
+        handler
+            .rejected(&headers, &body, &Rejection::MissingTopic)
+            .await;
+        return StatusCode::BAD_REQUEST;
+    };
+
+    deliver(&pubsub, handler.as_ref(), topic, headers, body).await
+}
+
+async fn subscription_delivery_handler<H: DeliveryHandler>(
+    Extension(pubsub): Extension<Arc<PubSub>>,
+    Extension(handler): Extension<Arc<H>>,
+    Path(subscription_id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let named_topic = link_topic(&headers);
+
+    let Some(topic) = pubsub
+        .subscription_topic(&subscription_id, named_topic.as_ref())
+        .await
+    else {
+        println!(
+            "Rejected delivery for unknown subscription {} ({:?})",
+            subscription_id,
+            named_topic.as_ref().map(|topic| topic.as_str())
+        );
+        handler
+            .rejected(
+                &headers,
+                &body,
+                &Rejection::UnknownSubscription(subscription_id),
+            )
+            .await;
+        return StatusCode::NOT_FOUND;
+    };
+
+    deliver(&pubsub, handler.as_ref(), topic, headers, body).await
+}
+
+/// The topic named in the `Link: <topic>; rel="self"` header.
+fn link_topic(headers: &HeaderMap) -> Option<reqwest::Url> {
+    headers
+        .get_all("Link")
+        .iter()
+        .filter_map(|link| link.to_str().ok())
+        .flat_map(parse_link_header)
+        .find(|(rel, _)| rel == "self")
+        .and_then(|(_, topic)| reqwest::Url::parse(&topic).ok())
+}
+
+/// Check the signature of a delivery for `topic` and pass it on to `handler`.
+async fn deliver<H: DeliveryHandler>(
+    pubsub: &PubSub,
+    handler: &H,
+    topic: reqwest::Url,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let signature = headers
+        .get("X-Hub-Signature")
+        .and_then(|signature| signature.to_str().ok());
+
+    if let Err(e) = pubsub.verify_signature(&topic, signature, &body).await {
+        println!(
+            "Rejected delivery for {:?}: {} ({:?})",
+            topic.as_str(),
+            e,
+            pubsub.rejected_deliveries()
+        );
+        handler
+            .rejected(&headers, &body, &Rejection::Signature(e))
+            .await;
+        return StatusCode::FORBIDDEN;
+    }
+
+    let topic = match pubsub.record_delivery(&topic).await {
+        Some(subscription) => {
+            handler
+                .subscription_changed(&subscription.topic, Some(&subscription))
+                .await;
+            subscription.topic
+        }
+        None => topic,
+    };
+
+    match handler
+        .deliver(Delivery {
+            topic,
+            headers,
+            body,
+        })
+        .await
+    {
+        Ok(()) => StatusCode::OK,
+        Err(e) => {
+            println!("Error handling delivery: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Parse `Link` header values (e.g. `<https://hub.example>; rel="hub"`) into `(rel, url)`
+/// pairs. A link with several relations yields one pair per relation.
+pub fn parse_link_header(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter_map(|link| {
+            let mut parts = link.split(';');
+            let url = parts
+                .next()?
+                .trim()
+                .strip_prefix('<')?
+                .strip_suffix('>')?
+                .to_string();
+            let rels = parts.find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("rel")
+                    .then(|| value.trim().trim_matches('"').to_string())
+            })?;
+
+            Some((url, rels))
+        })
+        .flat_map(|(url, rels)| {
+            rels.split_whitespace()
+                .map(|rel| (rel.to_ascii_lowercase(), url.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Verify a `method=hexdigest` signature as described by the WebSub spec.
+fn check_signature(secret: &str, signature: &str, body: &[u8]) -> Result<(), SignatureError> {
+    let malformed = || SignatureError::Malformed(signature.to_string());
+
+    let (method, digest) = signature.split_once('=').ok_or_else(malformed)?;
+    let digest = hex::decode(digest).map_err(|_| malformed())?;
+
+    let matches = match method {
+        "sha1" => verify_hmac::<Hmac<sha1::Sha1>>(secret, body, &digest),
+        "sha256" => verify_hmac::<Hmac<sha2::Sha256>>(secret, body, &digest),
+        "sha384" => verify_hmac::<Hmac<sha2::Sha384>>(secret, body, &digest),
+        "sha512" => verify_hmac::<Hmac<sha2::Sha512>>(secret, body, &digest),
+        _ => return Err(malformed()),
+    };
+
+    if matches {
+        Ok(())
+    } else {
+        Err(SignatureError::Mismatch)
+    }
+}
+
+fn verify_hmac<M: Mac + hmac::digest::KeyInit>(secret: &str, body: &[u8], digest: &[u8]) -> bool {
+    let mut mac = <M as hmac::digest::KeyInit>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.verify_slice(digest).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "key";
+    const BODY: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    #[test]
+    fn check_signature_accepts_valid_sha1() {
+        let signature = "sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9";
+
+        assert!(check_signature(SECRET, signature, BODY).is_ok());
+    }
+
+    #[test]
+    fn check_signature_accepts_valid_sha256() {
+        let signature = "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8";
+
+        assert!(check_signature(SECRET, signature, BODY).is_ok());
+    }
+
+    #[test]
+    fn check_signature_rejects_wrong_digest() {
+        let signature = "sha1=0000000000000000000000000000000000000000";
+
+        assert!(matches!(
+            check_signature(SECRET, signature, BODY),
+            Err(SignatureError::Mismatch)
+        ));
+        assert!(matches!(
+            check_signature(
+                "other",
+                "sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9",
+                BODY
+            ),
+            Err(SignatureError::Mismatch)
+        ));
+    }
+
+    #[test]
+    fn check_signature_rejects_unknown_algorithm() {
+        let signature = "md5=80070713463e7749b90c2dc24911e275";
+
+        assert!(matches!(
+            check_signature(SECRET, signature, BODY),
+            Err(SignatureError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn check_signature_rejects_malformed_header() {
+        for signature in [
+            "",
+            "sha1",
+            "de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9",
+            "sha1=not-hex",
+        ] {
+            assert!(
+                matches!(
+                    check_signature(SECRET, signature, BODY),
+                    Err(SignatureError::Malformed(_))
+                ),
+                "{:?} should be malformed",
+                signature
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_signature_rejects_missing_header() {
+        let pubsub = PubSub::new(reqwest::Url::parse("http://localhost/callback").unwrap());
+        let topic = reqwest::Url::parse("http://localhost/feed").unwrap();
+
+        assert!(matches!(
+            pubsub.verify_signature(&topic, None, BODY).await,
+            Err(SignatureError::Missing)
+        ));
+        assert_eq!(pubsub.rejected_deliveries().missing_signature, 1);
+        assert_eq!(pubsub.rejected_deliveries().invalid_signature, 0);
+    }
+
+    #[test]
+    fn parse_link_header_reads_multiple_links() {
+        let links = parse_link_header(
+            r#"<https://pubsubhubbub.appspot.com/>; rel="hub", <https://www.youtube.com/xml/feeds/videos.xml?channel_id=UC1>; rel="self""#,
+        );
+
+        assert_eq!(
+            links,
+            vec![
+                (
+                    "hub".to_string(),
+                    "https://pubsubhubbub.appspot.com/".to_string()
+                ),
+                (
+                    "self".to_string(),
+                    "https://www.youtube.com/xml/feeds/videos.xml?channel_id=UC1".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_link_header_splits_several_relations() {
+        let links = parse_link_header(r#"<https://hub.example/>; rel="hub self""#);
+
+        assert_eq!(
+            links,
+            vec![
+                ("hub".to_string(), "https://hub.example/".to_string()),
+                ("self".to_string(), "https://hub.example/".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_link_header_accepts_quoted_and_unquoted_rel() {
+        assert_eq!(
+            parse_link_header(r#"<https://hub.example/>; rel="hub""#),
+            parse_link_header("<https://hub.example/>; rel=hub"),
+        );
+        assert_eq!(
+            parse_link_header("<https://hub.example/>; REL=Hub"),
+            vec![("hub".to_string(), "https://hub.example/".to_string())]
+        );
+    }
+
+    #[test]
+    fn parse_link_header_ignores_extra_whitespace() {
+        let links = parse_link_header(
+            "  <https://hub.example/> ;  rel = \"hub\"  ,\t<https://feed.example/>;rel=self ",
+        );
+
+        assert_eq!(
+            links,
+            vec![
+                ("hub".to_string(), "https://hub.example/".to_string()),
+                ("self".to_string(), "https://feed.example/".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_link_header_skips_links_without_rel() {
+        assert!(parse_link_header("<https://hub.example/>; title=\"hub\"").is_empty());
+        assert!(parse_link_header("https://hub.example/; rel=hub").is_empty());
     }
 }
@@ -0,0 +1,243 @@
+use chrono::DateTime;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YoutubeLink {
+    #[serde(rename = "@rel", default)]
+    pub rel: String,
+    #[serde(rename = "@href")]
+    pub href: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YoutubeAuthor {
+    pub name: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YoutubeEntry {
+    pub id: String,
+    #[serde(rename = "videoId")]
+    pub video_id: String,
+    #[serde(rename = "channelId")]
+    pub channel_id: String,
+    pub title: String,
+    pub link: YoutubeLink,
+    pub author: YoutubeAuthor,
+    #[serde(deserialize_with = "de_time", serialize_with = "se_time", default)]
+    pub published: Option<DateTime<chrono::Utc>>,
+    #[serde(deserialize_with = "de_time", serialize_with = "se_time", default)]
+    pub updated: Option<DateTime<chrono::Utc>>,
+}
+
+/// Tombstone sent when a video or scheduled stream is removed (`<at:deleted-entry>`).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YoutubeDeletedEntry {
+    /// e.g. `yt:video:VIDEO_ID`
+    #[serde(rename = "@ref")]
+    pub reference: String,
+    #[serde(
+        rename = "@when",
+        deserialize_with = "de_time",
+        serialize_with = "se_time",
+        default
+    )]
+    pub when: Option<DateTime<chrono::Utc>>,
+    pub link: YoutubeLink,
+    pub by: YoutubeAuthor,
+}
+
+impl YoutubeDeletedEntry {
+    pub fn video_id(&self) -> Option<&str> {
+        self.reference.strip_prefix("yt:video:")
+    }
+}
+
+/// A single notification carried by a [`YoutubeFeed`].
+#[derive(Debug)]
+pub enum YoutubeNotification {
+    Entry(YoutubeEntry),
+    Deleted(YoutubeDeletedEntry),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YoutubeFeed {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub link: Vec<YoutubeLink>,
+    #[serde(deserialize_with = "de_time", serialize_with = "se_time", default)]
+    pub updated: Option<DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub entry: Vec<YoutubeEntry>,
+    #[serde(rename = "deleted-entry", default)]
+    pub deleted_entry: Vec<YoutubeDeletedEntry>,
+}
+
+impl YoutubeFeed {
+    /// The topic this feed was published for.
+    ///
+    /// Uses `<link rel="self">` when present. Tombstone deliveries often omit it, so
+    /// otherwise the channel's video feed is derived from the entries.
+    pub fn topic(&self) -> Option<reqwest::Url> {
+        if let Some(link) = self.link.iter().find(|link| link.rel == "self") {
+            return reqwest::Url::parse(&link.href).ok();
+        }
+
+        let channel_id = self
+            .entry
+            .iter()
+            .map(|entry| entry.channel_id.as_str())
+            .chain(
+                self.deleted_entry
+                    .iter()
+                    .filter_map(|deleted| deleted.by.uri.rsplit_once("/channel/"))
+                    .map(|(_, channel_id)| channel_id),
+            )
+            .next()?;
+
+        reqwest::Url::parse_with_params(
+            "https://www.youtube.com/xml/feeds/videos.xml",
+            &[("channel_id", channel_id)],
+        )
+        .ok()
+    }
+
+    pub fn into_notifications(self) -> impl Iterator<Item = YoutubeNotification> {
+        self.entry
+            .into_iter()
+            .map(YoutubeNotification::Entry)
+            .chain(
+                self.deleted_entry
+                    .into_iter()
+                    .map(YoutubeNotification::Deleted),
+            )
+    }
+}
+
+/// Deserialize a `DateTime` from an RFC 3339 date string.
+fn de_time<'de, D>(deserializer: D) -> Result<Option<DateTime<chrono::Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+
+    let dt: Result<DateTime<chrono::FixedOffset>, chrono::ParseError> =
+        chrono::DateTime::parse_from_rfc3339(&s);
+
+    match dt {
+        Ok(dt) => {
+            let dt = dt.with_timezone(&chrono::Utc);
+            Ok(Some(dt))
+        }
+        Err(e) => {
+            println!("Error parsing time ({}):  {}", s, e);
+            Ok(None)
+        }
+    }
+}
+
+/// Serialize a `DateTime` into an RFC 3339 date string as bytes.
+fn se_time<S>(dt: &Option<DateTime<chrono::Utc>>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match dt {
+        Some(dt) => Ok(s.serialize_str(&dt.to_rfc3339())?),
+        None => Ok(s.serialize_str("")?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOMBSTONE: &str = r#"<feed xmlns:at="http://purl.org/atompub/tombstones/1.0" xmlns="http://www.w3.org/2005/Atom">
+  <at:deleted-entry ref="yt:video:deleted0001" when="2024-03-01T12:00:00.000000+00:00">
+    <link href="https://www.youtube.com/watch?v=deleted0001"/>
+    <at:by>
+      <name>Channel</name>
+      <uri>https://www.youtube.com/channel/UCmockchannel000000000001</uri>
+    </at:by>
+  </at:deleted-entry>
+</feed>"#;
+
+    const MIXED: &str = r#"<feed xmlns:yt="http://www.youtube.com/xml/schemas/2015" xmlns:at="http://purl.org/atompub/tombstones/1.0" xmlns="http://www.w3.org/2005/Atom">
+  <link rel="self" href="https://www.youtube.com/xml/feeds/videos.xml?channel_id=UCmockchannel000000000001"/>
+  <title>YouTube video feed</title>
+  <updated>2024-03-01T12:00:00.000000+00:00</updated>
+  <entry>
+    <id>yt:video:upcoming001</id>
+    <yt:videoId>upcoming001</yt:videoId>
+    <yt:channelId>UCmockchannel000000000001</yt:channelId>
+    <title>Upcoming stream</title>
+    <link rel="alternate" href="https://www.youtube.com/watch?v=upcoming001"/>
+    <author>
+      <name>Channel</name>
+      <uri>https://www.youtube.com/channel/UCmockchannel000000000001</uri>
+    </author>
+    <published>2024-03-01T11:00:00+00:00</published>
+    <updated>2024-03-01T11:30:00.000000+00:00</updated>
+  </entry>
+  <at:deleted-entry ref="yt:video:deleted0001" when="2024-03-01T12:00:00.000000+00:00">
+    <link href="https://www.youtube.com/watch?v=deleted0001"/>
+    <at:by>
+      <name>Channel</name>
+      <uri>https://www.youtube.com/channel/UCmockchannel000000000001</uri>
+    </at:by>
+  </at:deleted-entry>
+</feed>"#;
+
+    #[test]
+    fn deserializes_a_tombstone() {
+        let feed: YoutubeFeed = quick_xml::de::from_str(TOMBSTONE).unwrap();
+
+        assert!(feed.entry.is_empty());
+        assert_eq!(feed.deleted_entry.len(), 1);
+        let deleted = &feed.deleted_entry[0];
+        assert_eq!(deleted.reference, "yt:video:deleted0001");
+        assert_eq!(deleted.video_id(), Some("deleted0001"));
+        assert_eq!(
+            deleted.when,
+            Some(
+                chrono::DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z")
+                    .unwrap()
+                    .with_timezone(&chrono::Utc)
+            )
+        );
+        assert_eq!(
+            deleted.link.href,
+            "https://www.youtube.com/watch?v=deleted0001"
+        );
+        assert_eq!(
+            feed.topic().unwrap().as_str(),
+            "https://www.youtube.com/xml/feeds/videos.xml?channel_id=UCmockchannel000000000001"
+        );
+
+        let notifications: Vec<_> = feed.into_notifications().collect();
+        assert!(matches!(
+            notifications[..],
+            [YoutubeNotification::Deleted(_)]
+        ));
+    }
+
+    #[test]
+    fn deserializes_entries_and_tombstones_in_one_feed() {
+        let feed: YoutubeFeed = quick_xml::de::from_str(MIXED).unwrap();
+
+        assert_eq!(feed.entry.len(), 1);
+        assert_eq!(feed.entry[0].video_id, "upcoming001");
+        assert_eq!(feed.deleted_entry.len(), 1);
+        assert_eq!(feed.deleted_entry[0].video_id(), Some("deleted0001"));
+
+        let notifications: Vec<_> = feed.into_notifications().collect();
+        assert!(matches!(
+            &notifications[..],
+            [
+                YoutubeNotification::Entry(entry),
+                YoutubeNotification::Deleted(deleted),
+            ] if entry.video_id == "upcoming001" && deleted.video_id() == Some("deleted0001")
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::archive;
+use crate::cron::LivestreamScheduler;
+use crate::data;
+use crate::dedupe::Deduplicator;
+use hololive_livestream_notifier_rs::config::env_or;
+use hololive_livestream_notifier_rs::pubsub;
+use mongodb::bson;
+use quick_xml::de::from_str;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+
+/// Durable queue of received WebSub deliveries, backed by the `deliveryJobs` collection.
+///
+/// The callback only enqueues the raw payload; workers process it and retry failures
+/// with exponential backoff. Jobs that fail `DELIVERY_MAX_ATTEMPTS` times, or that can
+/// never succeed, are moved to the `deadLetterDeliveries` collection.
+pub struct DeliveryQueue {
+    notify: Notify,
+    max_attempts: u32,
+    retry_base: chrono::Duration,
+    retry_max: chrono::Duration,
+    lock_duration: chrono::Duration,
+    poll_interval: Duration,
+}
+
+impl DeliveryQueue {
+    pub fn new() -> Self {
+        Self {
+            notify: Notify::new(),
+            max_attempts: env_or("DELIVERY_MAX_ATTEMPTS", 8),
+            retry_base: chrono::Duration::seconds(env_or("DELIVERY_RETRY_BASE_SECS", 30)),
+            retry_max: chrono::Duration::seconds(env_or("DELIVERY_RETRY_MAX_SECS", 60 * 60)),
+            lock_duration: chrono::Duration::minutes(5),
+            poll_interval: Duration::from_secs(10),
+        }
+    }
+
+    pub async fn enqueue(
+        &self,
+        topic: &str,
+        payload: String,
+        archive_id: Option<bson::oid::ObjectId>,
+    ) -> mongodb::error::Result<()> {
+        let now = bson::DateTime::now();
+        let job = data::models::DeliveryJob {
+            id: None,
+            topic: topic.to_string(),
+            payload,
+            received_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            locked_until: None,
+            last_error: None,
+            dead_lettered_at: None,
+            archive_id,
+        };
+
+        let id = data::Mongo::new().await.enqueue_delivery(&job).await?;
+        println!("Queued delivery {} for {:?}", id, topic);
+
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Process queued deliveries until the process exits.
+    pub async fn run_worker(
+        self: Arc<Self>,
+        livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+        deduplicator: Arc<Deduplicator>,
+    ) {
+        let mongo = data::Mongo::new().await;
+
+        loop {
+            match mongo.claim_delivery(self.lock_duration).await {
+                Ok(Some(job)) => {
+                    self.process(
+                        &mongo,
+                        Arc::clone(&livestream_scheduler),
+                        Arc::clone(&deduplicator),
+                        job,
+                    )
+                    .await
+                }
+                Ok(None) => {
+                    // Woken early by new deliveries, otherwise wait for retries to come due.
+                    let _ = tokio::time::timeout(self.poll_interval, self.notify.notified()).await;
+                }
+                Err(e) => {
+                    println!("Error claiming delivery job: {}", e);
+                    tokio::time::sleep(self.poll_interval).await;
+                }
+            }
+        }
+    }
+
+    async fn process(
+        &self,
+        mongo: &data::Mongo,
+        livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+        deduplicator: Arc<Deduplicator>,
+        mut job: data::models::DeliveryJob,
+    ) {
+        let Some(id) = job.id else {
+            return;
+        };
+
+        let yt_feed = match from_str::<pubsub::youtube::YoutubeFeed>(&job.payload) {
+            Ok(yt_feed) => yt_feed,
+            Err(e) => {
+                // Retrying will not make a malformed payload parse.
+                job.last_error = Some(format!("Error parsing feed: {}", e));
+                self.dead_letter(mongo, job).await;
+                return;
+            }
+        };
+
+        println!("Parsed feed: {:?}", yt_feed);
+
+        {
+            let yt_feed_json_str = serde_json::to_string_pretty(&yt_feed).unwrap().to_string();
+            let yt_feed_json_str = format!("Processing feed:\n```json\n{}\n```", yt_feed_json_str);
+            tokio::spawn(crate::send_message_to_developer(yt_feed_json_str));
+        }
+
+        let failures = crate::process_feed(
+            livestream_scheduler,
+            deduplicator,
+            yt_feed,
+            crate::youtube::Priority::High,
+        )
+        .await;
+
+        if failures.is_empty() {
+            if let Err(e) = mongo.complete_delivery(&id).await {
+                println!("Error completing delivery job {}: {}", id, e);
+            }
+            archive::record_outcome(job.archive_id, "processed").await;
+            return;
+        }
+
+        let error = failures.join("\n");
+        job.attempts += 1;
+        job.last_error = Some(error.clone());
+
+        if job.attempts >= self.max_attempts {
+            self.dead_letter(mongo, job).await;
+            return;
+        }
+
+        let next_attempt_at = chrono::Utc::now() + self.backoff(job.attempts);
+        println!(
+            "Delivery job {} failed (attempt {}), retrying at {}:\n{}",
+            id, job.attempts, next_attempt_at, error
+        );
+
+        if let Err(e) = mongo
+            .retry_delivery(
+                &id,
+                job.attempts,
+                bson::DateTime::from_chrono(next_attempt_at),
+                &error,
+            )
+            .await
+        {
+            println!("Error rescheduling delivery job {}: {}", id, e);
+        }
+
+        archive::record_outcome(
+            job.archive_id,
+            &format!("failed (attempt {}): {}", job.attempts, error),
+        )
+        .await;
+    }
+
+    /// `DELIVERY_RETRY_BASE_SECS` doubled for every failed attempt, capped at
+    /// `DELIVERY_RETRY_MAX_SECS`.
+    fn backoff(&self, attempts: u32) -> chrono::Duration {
+        2_i32
+            .checked_pow(attempts.saturating_sub(1))
+            .and_then(|factor| self.retry_base.checked_mul(factor))
+            .map_or(self.retry_max, |delay| delay.min(self.retry_max))
+    }
+
+    async fn dead_letter(&self, mongo: &data::Mongo, mut job: data::models::DeliveryJob) {
+        job.dead_lettered_at = Some(bson::DateTime::now());
+        job.locked_until = None;
+
+        let message = format!(
+            "Gave up on delivery for {} after {} attempt(s):\n{}",
+            job.topic,
+            job.attempts,
+            job.last_error.as_deref().unwrap_or_default()
+        );
+        println!("{}", message);
+        tokio::spawn(crate::send_message_to_developer(message));
+
+        if let Err(e) = mongo.dead_letter_delivery(&job).await {
+            println!("Error dead-lettering delivery job {:?}: {}", job.id, e);
+        }
+
+        archive::record_outcome(
+            job.archive_id,
+            &format!(
+                "dead-lettered: {}",
+                job.last_error.as_deref().unwrap_or_default()
+            ),
+        )
+        .await;
+    }
+}
@@ -0,0 +1,387 @@
+use crate::data;
+use futures::StreamExt;
+use hololive_livestream_notifier_rs::config::env_or;
+use hololive_livestream_notifier_rs::pubsub::{self, Mode, SubscriptionState};
+use mongodb::bson::oid::ObjectId;
+use mongodb::change_stream::event::{OperationType, ResumeToken};
+use reqwest::Url;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Keep the WebSub subscriptions in line with the `feeds` collection.
+///
+/// Runs immediately and then every `FEED_RECONCILE_INTERVAL_SECS`. Requests that the hub
+/// has not confirmed within `FEED_RECONCILE_PENDING_TIMEOUT_SECS` are sent again.
+pub async fn reconcile_feeds(pubsub: Arc<pubsub::PubSub>) {
+    let interval = Duration::from_secs(env_or("FEED_RECONCILE_INTERVAL_SECS", 15 * 60));
+    let pending_timeout =
+        chrono::Duration::seconds(env_or("FEED_RECONCILE_PENDING_TIMEOUT_SECS", 10 * 60));
+
+    loop {
+        if let Err(e) = reconcile(&pubsub, pending_timeout).await {
+            println!("Error reconciling feeds: {}", e);
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn reconcile(
+    pubsub: &pubsub::PubSub,
+    pending_timeout: chrono::Duration,
+) -> mongodb::error::Result<()> {
+    let feeds = data::Mongo::new().await.get_feeds().await?;
+    let subscriptions: HashMap<_, _> = pubsub
+        .subscriptions()
+        .await
+        .into_iter()
+        .map(|subscription| (subscription.topic.clone(), subscription))
+        .collect();
+    let stale_before = chrono::Utc::now() - pending_timeout;
+    let is_stale = |subscription: &pubsub::Subscription| {
+        subscription
+            .requested_at
+            .is_none_or(|requested_at| requested_at < stale_before)
+    };
+
+    let mut wanted = HashSet::new();
+    let mut to_subscribe = Vec::new();
+    let mut to_unsubscribe = Vec::new();
+
+    for feed in &feeds {
+        wanted.insert(feed.topic_url.clone());
+
+        let needs_subscribe = match subscriptions.get(&feed.topic_url) {
+            None => true,
+            Some(subscription) => match (subscription.intent, subscription.state) {
+                // Added back while an unsubscribe was in flight.
+                (Some(Mode::Unsubscribe), _) => true,
+                (Some(Mode::Subscribe), _) => is_stale(subscription),
+                (None, SubscriptionState::Pending | SubscriptionState::Denied) => true,
+                // Lapsed leases are renewed by `renew_subscriptions`.
+                (None, SubscriptionState::Verified | SubscriptionState::Expired) => false,
+            },
+        };
+
+        if needs_subscribe {
+            to_subscribe.push(feed);
+        }
+    }
+
+    for subscription in subscriptions.values() {
+        if wanted.contains(&subscription.topic) {
+            continue;
+        }
+
+        if subscription.intent != Some(Mode::Unsubscribe) || is_stale(subscription) {
+            to_unsubscribe.push(subscription.topic.clone());
+        }
+    }
+
+    subscribe_all(
+        pubsub,
+        to_subscribe
+            .iter()
+            .map(|feed| feed.topic_url.clone())
+            .collect(),
+        "feeds",
+    )
+    .await;
+
+    for topic in &to_unsubscribe {
+        unsubscribe(pubsub, topic).await;
+    }
+
+    if !to_subscribe.is_empty() || !to_unsubscribe.is_empty() {
+        println!(
+            "Reconciled {} feed(s): {} subscribe and {} unsubscribe request(s)",
+            feeds.len(),
+            to_subscribe.len(),
+            to_unsubscribe.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Apply edits to the `feeds` collection as they happen, using a change stream.
+///
+/// Inserted feeds are subscribed to, deleted feeds are unsubscribed from and a changed
+/// `topicURL` moves the subscription. The stream is reopened with backoff if it fails,
+/// resuming after the last change seen.
+pub async fn watch_feeds(pubsub: Arc<pubsub::PubSub>) {
+    let min_retry_delay = Duration::from_secs(5);
+    let max_retry_delay = Duration::from_secs(5 * 60);
+
+    let mut resume_token = None;
+    let mut retry_delay = min_retry_delay;
+
+    loop {
+        match watch(&pubsub, &mut resume_token, &mut retry_delay).await {
+            Ok(()) => println!("Feeds change stream closed"),
+            Err(e) => println!("Error watching feeds: {}", e),
+        }
+
+        tokio::time::sleep(retry_delay).await;
+        retry_delay = (retry_delay * 2).min(max_retry_delay);
+    }
+}
+
+async fn watch(
+    pubsub: &pubsub::PubSub,
+    resume_token: &mut Option<ResumeToken>,
+    retry_delay: &mut Duration,
+) -> mongodb::error::Result<()> {
+    let mongo = data::Mongo::new().await;
+    let mut stream = mongo.watch_feeds(resume_token.clone()).await?;
+
+    // Deletes only carry the `_id`, so remember which topic every feed points at. The
+    // snapshot is taken after opening the stream so no change falls in between.
+    let mut topics: HashMap<ObjectId, Url> = mongo
+        .get_feeds()
+        .await?
+        .into_iter()
+        .filter_map(|feed| Some((feed.id?, feed.topic_url)))
+        .collect();
+
+    println!("Watching feeds collection for changes");
+    *retry_delay = Duration::from_secs(5);
+
+    while let Some(event) = stream.next().await {
+        let event = event?;
+
+        match event.operation_type {
+            OperationType::Insert | OperationType::Update | OperationType::Replace => {
+                // Missing when the feed was deleted before the lookup; its delete follows.
+                let Some(feed) = event.full_document else {
+                    continue;
+                };
+                let Some(id) = feed.id else {
+                    continue;
+                };
+
+                let previous = topics.insert(id, feed.topic_url.clone());
+                if previous.as_ref() == Some(&feed.topic_url) {
+                    continue;
+                }
+
+                println!(
+                    "Feed {:?} {:?} now points at {:?}",
+                    feed.first_name,
+                    feed.last_name,
+                    feed.topic_url.as_str()
+                );
+
+                if !is_subscribed(pubsub, &feed.topic_url).await {
+                    let retry = SubscribeRetry::from_env();
+                    // Reported by the next reconciliation if it still fails.
+                    let _ = subscribe_with_retry(pubsub, &feed.topic_url, &retry).await;
+                }
+                if let Some(previous) = previous {
+                    unsubscribe_if_unused(pubsub, &topics, &previous).await;
+                }
+            }
+            OperationType::Delete => {
+                let id = event
+                    .document_key
+                    .as_ref()
+                    .and_then(|key| key.get_object_id("_id").ok());
+                let Some(topic) = id.and_then(|id| topics.remove(&id)) else {
+                    continue;
+                };
+
+                println!("Feed for {:?} was deleted", topic.as_str());
+                unsubscribe_if_unused(pubsub, &topics, &topic).await;
+            }
+            OperationType::Invalidate => {
+                // The collection was dropped or renamed, so the stream cannot be resumed.
+                *resume_token = None;
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        *resume_token = stream.resume_token();
+    }
+
+    Ok(())
+}
+
+/// Whether a subscription to `topic` is verified or being requested.
+async fn is_subscribed(pubsub: &pubsub::PubSub, topic: &Url) -> bool {
+    pubsub
+        .subscriptions()
+        .await
+        .iter()
+        .find(|subscription| &subscription.topic == topic)
+        .is_some_and(|subscription| match subscription.intent {
+            Some(mode) => mode == Mode::Subscribe,
+            None => subscription.state == SubscriptionState::Verified,
+        })
+}
+
+async fn unsubscribe_if_unused(
+    pubsub: &pubsub::PubSub,
+    topics: &HashMap<ObjectId, Url>,
+    topic: &Url,
+) {
+    if !topics.values().any(|other| other == topic) {
+        unsubscribe(pubsub, topic).await;
+    }
+}
+
+/// How subscription requests are sent.
+///
+/// `SUBSCRIBE_CONCURRENCY` requests are in flight at a time. A failed request is retried
+/// up to `SUBSCRIBE_MAX_ATTEMPTS` times in all, waiting `SUBSCRIBE_RETRY_BASE_SECS`
+/// doubled for every failed attempt (at most `SUBSCRIBE_RETRY_MAX_SECS`), with jitter so
+/// that the retries of a large roster do not hit the hub at the same time.
+struct SubscribeRetry {
+    concurrency: usize,
+    max_attempts: u32,
+    base: Duration,
+    max: Duration,
+}
+
+impl SubscribeRetry {
+    fn from_env() -> Self {
+        Self {
+            concurrency: env_or("SUBSCRIBE_CONCURRENCY", 8),
+            max_attempts: env_or("SUBSCRIBE_MAX_ATTEMPTS", 5),
+            base: Duration::from_secs(env_or("SUBSCRIBE_RETRY_BASE_SECS", 2)),
+            max: Duration::from_secs(env_or("SUBSCRIBE_RETRY_MAX_SECS", 60)),
+        }
+    }
+
+    /// Half of the backoff for `attempt`, plus a random part of the other half.
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max);
+
+        backoff / 2 + backoff.mul_f64(rand::random::<f64>() / 2.0)
+    }
+}
+
+/// Subscribe to every topic in `topics` and send the developer a summary of how many
+/// subscriptions were verified, are still waiting for the hub, or failed.
+///
+/// `reason` says what the requests were for in the summary. The summary is sent
+/// `SUBSCRIBE_REPORT_DELAY_SECS` after the last request, to give the hub time to verify.
+pub async fn subscribe_all(pubsub: &pubsub::PubSub, topics: Vec<Url>, reason: &str) {
+    if topics.is_empty() {
+        return;
+    }
+
+    let retry = SubscribeRetry::from_env();
+    let report_delay = Duration::from_secs(env_or("SUBSCRIBE_REPORT_DELAY_SECS", 30));
+
+    let failures: Vec<(Url, String)> = futures::stream::iter(topics.clone())
+        .map(|topic| {
+            let retry = &retry;
+            async move {
+                subscribe_with_retry(pubsub, &topic, retry)
+                    .await
+                    .err()
+                    .map(|e| (topic, e))
+            }
+        })
+        .buffer_unordered(retry.concurrency)
+        .filter_map(|failure| async move { failure })
+        .collect()
+        .await;
+
+    tokio::time::sleep(report_delay).await;
+
+    let subscriptions: HashMap<_, _> = pubsub
+        .subscriptions()
+        .await
+        .into_iter()
+        .map(|subscription| (subscription.topic.clone(), subscription))
+        .collect();
+    // A failed request can leave the previous subscription verified, so failed topics are
+    // only counted as failed.
+    let failed: HashSet<_> = failures.iter().map(|(topic, _)| topic).collect();
+    let (subscribed, pending) = topics.iter().filter(|topic| !failed.contains(topic)).fold(
+        (0, 0),
+        |(subscribed, pending), topic| {
+            let verified = subscriptions.get(topic).is_some_and(|subscription| {
+                subscription.intent.is_none() && subscription.state == SubscriptionState::Verified
+            });
+            if verified {
+                (subscribed + 1, pending)
+            } else {
+                (subscribed, pending + 1)
+            }
+        },
+    );
+
+    let mut report = format!(
+        "Subscription requests for {}: {} subscribed, {} pending, {} failed",
+        reason,
+        subscribed,
+        pending,
+        failures.len()
+    );
+    for (topic, e) in failures.iter().take(10) {
+        report.push_str(&format!("\n- {}: {}", topic, e));
+    }
+    if failures.len() > 10 {
+        report.push_str(&format!("\n- and {} more", failures.len() - 10));
+    }
+
+    println!("{}", report);
+    tokio::spawn(crate::send_message_to_developer(report));
+}
+
+/// Send a subscription request for `topic`, retrying failures as set by `retry`.
+async fn subscribe_with_retry(
+    pubsub: &pubsub::PubSub,
+    topic: &Url,
+    retry: &SubscribeRetry,
+) -> Result<(), String> {
+    let mut attempt = 1;
+
+    loop {
+        match pubsub.subscribe(topic.clone()).await {
+            Ok(subscription) => {
+                crate::save_subscription(topic, subscription.as_ref()).await;
+                println!("Sent subscription request for {:?}", topic.as_str());
+                return Ok(());
+            }
+            Err(e) if attempt >= retry.max_attempts => {
+                println!(
+                    "Giving up subscribing to {:?} after {} attempt(s): {:?}",
+                    topic.as_str(),
+                    attempt,
+                    e
+                );
+                return Err(e.to_string());
+            }
+            Err(e) => {
+                let delay = retry.delay(attempt);
+                println!(
+                    "Error subscribing to {:?} (attempt {}), retrying in {:?}: {:?}",
+                    topic.as_str(),
+                    attempt,
+                    delay,
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+async fn unsubscribe(pubsub: &pubsub::PubSub, topic: &Url) {
+    match pubsub.unsubscribe(topic.clone()).await {
+        Ok(subscription) => {
+            crate::save_subscription(topic, subscription.as_ref()).await;
+            println!("Sent unsubscription request for {:?}", topic.as_str());
+        }
+        Err(e) => println!("Error unsubscribing from {:?}: {:?}", topic.as_str(), e),
+    }
+}
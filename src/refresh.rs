@@ -0,0 +1,193 @@
+use crate::cron::LivestreamScheduler;
+use crate::data;
+use crate::youtube;
+use chrono::{DateTime, Utc};
+use hololive_livestream_notifier_rs::config::env_or;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Look up upcoming livestreams again to catch reschedules the hub never told us about.
+///
+/// Every `UPCOMING_REFRESH_TICK_SECS`, the streams that are due are looked up together. A
+/// stream is due every `UPCOMING_REFRESH_NEAR_SECS` in the hour before it starts, every
+/// `UPCOMING_REFRESH_SOON_SECS` in the day before, and every `UPCOMING_REFRESH_FAR_SECS`
+/// otherwise. When the start time changed, the stream is saved, its notifications are
+/// rescheduled and its announcements are edited.
+pub async fn refresh_upcoming(livestream_scheduler: Arc<Mutex<LivestreamScheduler>>) {
+    let tick = Duration::from_secs(env_or("UPCOMING_REFRESH_TICK_SECS", 60));
+    let intervals = RefreshIntervals {
+        near: chrono::Duration::seconds(env_or("UPCOMING_REFRESH_NEAR_SECS", 5 * 60)),
+        soon: chrono::Duration::seconds(env_or("UPCOMING_REFRESH_SOON_SECS", 30 * 60)),
+        far: chrono::Duration::seconds(env_or("UPCOMING_REFRESH_FAR_SECS", 6 * 60 * 60)),
+    };
+
+    let client = youtube::YoutubeClient::new(data::Mongo::new().await);
+    let mut last_refreshed = HashMap::new();
+
+    loop {
+        tokio::time::sleep(tick).await;
+
+        if let Err(e) = refresh(
+            &client,
+            &intervals,
+            &mut last_refreshed,
+            Arc::clone(&livestream_scheduler),
+        )
+        .await
+        {
+            println!("Error refreshing upcoming livestreams: {}", e);
+        }
+    }
+}
+
+struct RefreshIntervals {
+    near: chrono::Duration,
+    soon: chrono::Duration,
+    far: chrono::Duration,
+}
+
+impl RefreshIntervals {
+    /// How long to wait between lookups of a stream that starts in `until_start`.
+    fn interval(&self, until_start: chrono::Duration) -> chrono::Duration {
+        if until_start <= chrono::Duration::hours(1) {
+            self.near
+        } else if until_start <= chrono::Duration::days(1) {
+            self.soon
+        } else {
+            self.far
+        }
+    }
+}
+
+/// Look up the upcoming streams that are due. `last_refreshed` holds when each stream was
+/// last looked up, by URL.
+async fn refresh(
+    client: &youtube::YoutubeClient,
+    intervals: &RefreshIntervals,
+    last_refreshed: &mut HashMap<String, DateTime<Utc>>,
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+) -> mongodb::error::Result<()> {
+    let now = Utc::now();
+    let upcoming = data::Mongo::new()
+        .await
+        .get_upcoming_livestreams(now)
+        .await?;
+
+    last_refreshed.retain(|url, _| upcoming.iter().any(|livestream| &livestream.url == url));
+
+    let due: Vec<_> = upcoming
+        .into_iter()
+        .filter(|livestream| {
+            let interval = intervals.interval(livestream.date.to_chrono() - now);
+            last_refreshed
+                .get(&livestream.url)
+                .is_none_or(|refreshed_at| now - *refreshed_at >= interval)
+        })
+        .collect();
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    let video_ids: Vec<_> = due
+        .iter()
+        .filter_map(|livestream| crate::video_id(&livestream.url))
+        .collect();
+    let mut metadata = client
+        .get_videos_metadata(&video_ids, youtube::Priority::Low)
+        .await;
+
+    for livestream in due {
+        let Some(result) =
+            crate::video_id(&livestream.url).and_then(|video_id| metadata.remove(video_id))
+        else {
+            continue;
+        };
+
+        let url = livestream.url.clone();
+        match result {
+            // Try again on the next tick.
+            Err(youtube::YoutubeError::Deferred) => continue,
+            Err(e) => println!("Error refreshing livestream {}: {}", url, e),
+            Ok(metadata) => {
+                if let Err(e) =
+                    reschedule(Arc::clone(&livestream_scheduler), livestream, metadata).await
+                {
+                    println!("Error rescheduling livestream {}: {}", url, e);
+                }
+            }
+        }
+        last_refreshed.insert(url, now);
+    }
+
+    Ok(())
+}
+
+/// Move `livestream` to the start time in `metadata` if it changed.
+pub async fn reschedule(
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    mut livestream: data::models::Livestream,
+    metadata: youtube::VideoMetadata,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(stream_dt) = metadata.livestream_start_dt else {
+        println!("No longer a livestream ({})", livestream.url);
+        return Ok(());
+    };
+
+    // Started streams are left to their notifications.
+    if metadata.actual_start_time.is_some()
+        || stream_dt.timestamp_millis() == livestream.date.timestamp_millis()
+    {
+        return Ok(());
+    }
+
+    if stream_dt < Utc::now() {
+        println!(
+            "Livestream moved to {}, which has already passed ({})",
+            stream_dt, livestream.url
+        );
+        return Ok(());
+    }
+
+    let previous_dt = livestream.date.to_chrono();
+    println!(
+        "Livestream rescheduled from {} to {} ({})",
+        previous_dt, stream_dt, livestream.url
+    );
+
+    livestream.title = metadata.title;
+    livestream.author = metadata.channel_title;
+    livestream.date = mongodb::bson::DateTime::from_chrono(stream_dt);
+    livestream.updated = mongodb::bson::DateTime::now();
+
+    // Streams announced before announcements were recorded get a new announcement.
+    let failed_edits = if livestream.announcements.is_empty() {
+        livestream.announcements = crate::send_will_livestream_message(&livestream).await?;
+        0
+    } else {
+        crate::edit_will_livestream_messages(&livestream).await
+    };
+
+    data::Mongo::new()
+        .await
+        .upsert_livestream(&livestream)
+        .await?;
+
+    let url = livestream.url.clone();
+    crate::setup_livestream_notifications(livestream_scheduler, livestream).await?;
+
+    let mut message = format!(
+        "Livestream {} was rescheduled from {} to {} without a notification",
+        url, previous_dt, stream_dt
+    );
+    if failed_edits > 0 {
+        message.push_str(&format!(
+            " ({} announcement(s) could not be edited)",
+            failed_edits
+        ));
+    }
+    tokio::spawn(crate::send_message_to_developer(message));
+
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use crate::cron::LivestreamScheduler;
+use crate::data;
+use crate::dedupe::Deduplicator;
+use crate::{poll, reconcile};
+use chrono::{DateTime, Utc};
+use hololive_livestream_notifier_rs::config::env_or;
+use hololive_livestream_notifier_rs::pubsub;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Catch subscriptions that the hub dropped without telling us.
+///
+/// Every `SILENCE_CHECK_INTERVAL_SECS`, each active feed that has not been heard from for
+/// `SILENCE_THRESHOLD_SECS` (no delivery, and no subscription request or verification) is
+/// resubscribed and its feed fetched directly. Entries that were never delivered are
+/// processed and reported to the developer.
+pub async fn watch_silence(
+    pubsub: Arc<pubsub::PubSub>,
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    deduplicator: Arc<Deduplicator>,
+) {
+    let check_interval = Duration::from_secs(env_or("SILENCE_CHECK_INTERVAL_SECS", 60 * 60));
+    let threshold = chrono::Duration::seconds(env_or("SILENCE_THRESHOLD_SECS", 2 * 24 * 60 * 60));
+
+    let client = reqwest::Client::new();
+
+    loop {
+        tokio::time::sleep(check_interval).await;
+
+        if let Err(e) = check(
+            &pubsub,
+            &client,
+            threshold,
+            Arc::clone(&livestream_scheduler),
+            Arc::clone(&deduplicator),
+        )
+        .await
+        {
+            println!("Error checking feeds for silence: {}", e);
+        }
+    }
+}
+
+async fn check(
+    pubsub: &pubsub::PubSub,
+    client: &reqwest::Client,
+    threshold: chrono::Duration,
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    deduplicator: Arc<Deduplicator>,
+) -> mongodb::error::Result<()> {
+    let feeds = data::Mongo::new().await.get_feeds().await?;
+    let subscriptions: HashMap<_, _> = pubsub
+        .subscriptions()
+        .await
+        .into_iter()
+        .map(|subscription| (subscription.topic.clone(), subscription))
+        .collect();
+    let silent_since = Utc::now() - threshold;
+
+    // Feeds without a subscription are left to the reconciliation.
+    let silent: Vec<_> = feeds
+        .iter()
+        .filter(|feed| feed.active)
+        .filter_map(|feed| {
+            let subscription = subscriptions.get(&feed.topic_url)?;
+            let last_heard = [
+                subscription.last_delivery_at,
+                subscription.last_challenge_at,
+                subscription.requested_at,
+            ]
+            .into_iter()
+            .flatten()
+            .max()?;
+
+            // Entries since the last delivery (or since we subscribed) should have arrived.
+            let expected_since = subscription
+                .last_delivery_at
+                .or(subscription.last_challenge_at)
+                .unwrap_or(last_heard);
+
+            (last_heard < silent_since).then_some((feed, expected_since))
+        })
+        .collect();
+
+    if silent.is_empty() {
+        return Ok(());
+    }
+
+    println!(
+        "{} feed(s) have been silent since {}",
+        silent.len(),
+        silent_since
+    );
+
+    reconcile::subscribe_all(
+        pubsub,
+        silent
+            .iter()
+            .map(|(feed, _)| feed.topic_url.clone())
+            .collect(),
+        "silent feeds",
+    )
+    .await;
+
+    for (feed, expected_since) in silent {
+        let result = catch_up(
+            client,
+            &feed.topic_url,
+            expected_since,
+            Arc::clone(&livestream_scheduler),
+            Arc::clone(&deduplicator),
+        )
+        .await;
+
+        match result {
+            Ok(missed) if missed.is_empty() => {}
+            Ok(missed) => {
+                let message = format!(
+                    "No deliveries for {} {} since {}, but their feed has entries we never received:\n{}",
+                    feed.first_name,
+                    feed.last_name,
+                    expected_since,
+                    missed.join("\n")
+                );
+                println!("{}", message);
+                tokio::spawn(crate::send_message_to_developer(message));
+            }
+            Err(e) => println!(
+                "Error polling silent feed {:?}: {}",
+                feed.topic_url.as_str(),
+                e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetch `topic` and process the entries updated after `expected_since` that were never
+/// processed. Returns a line for every such entry, and for every one that failed.
+async fn catch_up(
+    client: &reqwest::Client,
+    topic: &reqwest::Url,
+    expected_since: DateTime<Utc>,
+    livestream_scheduler: Arc<Mutex<LivestreamScheduler>>,
+    deduplicator: Arc<Deduplicator>,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mongo = data::Mongo::new().await;
+    let mut yt_feed = poll::fetch_feed(client, topic).await?;
+
+    let mut missed = Vec::new();
+    for entry in std::mem::take(&mut yt_feed.entry) {
+        let Some(updated) = entry.updated.or(entry.published) else {
+            continue;
+        };
+        if updated <= expected_since {
+            continue;
+        }
+
+        let key = Deduplicator::key(&entry.video_id, updated.timestamp_millis());
+        if !mongo.is_processed_entry(&key).await? {
+            missed.push(entry);
+        }
+    }
+
+    let mut lines: Vec<_> = missed
+        .iter()
+        .map(|entry| format!("- {} ({})", entry.title, entry.link.href))
+        .collect();
+
+    if !missed.is_empty() {
+        yt_feed.entry = missed;
+        let failures = crate::process_feed(
+            livestream_scheduler,
+            deduplicator,
+            yt_feed,
+            crate::youtube::Priority::Low,
+        )
+        .await;
+        lines.extend(
+            failures
+                .into_iter()
+                .map(|failure| format!("Error processing {}", failure)),
+        );
+    }
+
+    Ok(lines)
+}
@@ -1,69 +1,553 @@
-#[derive(Debug)]
+use chrono::{DateTime, Utc};
+use hololive_livestream_notifier_rs::config::{env_or, env_parse};
+use hololive_livestream_notifier_rs::youtube_api::{self, QuotaTracker};
+pub use hololive_livestream_notifier_rs::youtube_api::{key_label, YoutubeError};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
 
+#[derive(Debug)]
+#[allow(dead_code)]
 pub struct VideoMetadata {
+    pub id: String,
     pub title: String,
     pub description: String,
     pub channel_id: String,
     pub channel_title: String,
-    pub livestream_start_dt: chrono::DateTime<chrono::Utc>,
+    pub published_at: Option<DateTime<Utc>>,
+    /// Keyed by size, e.g. `default`, `high` or `maxres`.
+    pub thumbnails: HashMap<String, Thumbnail>,
+    pub tags: Vec<String>,
+    pub live_broadcast_content: LiveBroadcastContent,
+    /// Scheduled start. `None` for videos that are not livestreams.
+    pub livestream_start_dt: Option<DateTime<Utc>>,
+    pub actual_start_time: Option<DateTime<Utc>>,
+    pub actual_end_time: Option<DateTime<Utc>>,
+    /// Only reported while the stream is live.
+    pub concurrent_viewers: Option<u64>,
+    /// Zero for streams that have not ended yet.
+    pub duration: Option<chrono::Duration>,
+    pub statistics: VideoStatistics,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
+pub struct Thumbnail {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LiveBroadcastContent {
+    Live,
+    Upcoming,
+    #[default]
+    #[serde(rename = "none", other)]
+    NotLive,
+}
+
+/// Counts are missing when the channel hides them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[allow(dead_code)]
+pub struct VideoStatistics {
+    #[serde(default, deserialize_with = "de_count")]
+    pub view_count: Option<u64>,
+    #[serde(default, deserialize_with = "de_count")]
+    pub like_count: Option<u64>,
+    #[serde(default, deserialize_with = "de_count")]
+    pub comment_count: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VideoListResponse {
+    #[serde(default)]
+    items: Vec<Video>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Video {
+    id: String,
+    snippet: VideoSnippet,
+    content_details: Option<VideoContentDetails>,
+    #[serde(default)]
+    statistics: VideoStatistics,
+    live_streaming_details: Option<LiveStreamingDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VideoSnippet {
+    published_at: Option<DateTime<Utc>>,
+    channel_id: String,
+    title: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    thumbnails: HashMap<String, Thumbnail>,
+    channel_title: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    live_broadcast_content: LiveBroadcastContent,
+}
+
+#[derive(Debug, Deserialize)]
+struct VideoContentDetails {
+    /// ISO 8601, e.g. `PT1H2M3S`.
+    duration: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LiveStreamingDetails {
+    actual_start_time: Option<DateTime<Utc>>,
+    actual_end_time: Option<DateTime<Utc>>,
+    scheduled_start_time: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "de_count")]
+    concurrent_viewers: Option<u64>,
+}
+
+impl From<Video> for VideoMetadata {
+    fn from(video: Video) -> Self {
+        let live_streaming_details = video.live_streaming_details;
+
+        Self {
+            id: video.id,
+            title: video.snippet.title,
+            description: video.snippet.description,
+            channel_id: video.snippet.channel_id,
+            channel_title: video.snippet.channel_title,
+            published_at: video.snippet.published_at,
+            thumbnails: video.snippet.thumbnails,
+            tags: video.snippet.tags,
+            live_broadcast_content: video.snippet.live_broadcast_content,
+            livestream_start_dt: live_streaming_details
+                .as_ref()
+                .and_then(|details| details.scheduled_start_time),
+            actual_start_time: live_streaming_details
+                .as_ref()
+                .and_then(|details| details.actual_start_time),
+            actual_end_time: live_streaming_details
+                .as_ref()
+                .and_then(|details| details.actual_end_time),
+            concurrent_viewers: live_streaming_details
+                .as_ref()
+                .and_then(|details| details.concurrent_viewers),
+            duration: video
+                .content_details
+                .and_then(|content_details| content_details.duration)
+                .and_then(|duration| parse_duration(&duration)),
+            statistics: video.statistics,
+        }
+    }
+}
+
+/// The most ids `videos.list` accepts in one request.
+pub const MAX_IDS_PER_REQUEST: usize = 50;
+
+/// Quota units a `videos.list` request costs, however many ids it asks for.
+const VIDEOS_LIST_COST: i64 = 1;
+
+/// Whether a request may be put off when the quota is running low.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Always sent, e.g. to process a delivery.
+    High,
+    /// Deferred once `YOUTUBE_QUOTA_LOW_PRIORITY_UNITS` are spent, e.g. refreshes and
+    /// backfills.
+    Low,
+}
+
+/// The daily quota and how much of it low-priority requests may use.
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaBudget {
+    /// `YOUTUBE_QUOTA_BUDGET`, by default YouTube's default quota of 10000 units for every
+    /// API key.
+    pub units: i64,
+    /// `YOUTUBE_QUOTA_LOW_PRIORITY_UNITS`, by default 80% of the budget.
+    pub low_priority_units: i64,
+}
+
+impl QuotaBudget {
+    pub fn from_env() -> Self {
+        let units = env_parse("YOUTUBE_QUOTA_BUDGET")
+            .unwrap_or_else(|| 10_000 * api_keys().len().max(1) as i64);
+        let low_priority_units = env_or("YOUTUBE_QUOTA_LOW_PRIORITY_UNITS", units * 8 / 10);
+
+        Self {
+            units,
+            low_priority_units,
+        }
+    }
+}
+
+/// The API keys to use, in order: `YOUTUBE_API_KEYS` (comma separated), otherwise
+/// `YOUTUBE_API_KEY`. Blank variables count as unset.
+pub fn api_keys() -> Vec<String> {
+    let env = |name: &str| {
+        std::env::var(name)
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+    };
+    env("YOUTUBE_API_KEYS")
+        .or_else(|| env("YOUTUBE_API_KEY"))
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|api_key| !api_key.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The quota day `time` falls on. The quota resets at midnight Pacific time.
+pub fn quota_day(time: DateTime<Utc>) -> String {
+    time.with_timezone(&chrono_tz::America::Los_Angeles)
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+/// The quota spent today.
+pub async fn quota_usage() -> mongodb::error::Result<crate::data::models::QuotaUsage> {
+    crate::data::Mongo::new()
+        .await
+        .get_quota_usage(&quota_day(Utc::now()))
+        .await
+}
+
+/// Records what each API key spent today in `youtubeQuota`.
+struct MongoQuotaTracker<'a> {
+    mongo: &'a crate::data::Mongo,
+    day: &'a str,
+}
+
+impl QuotaTracker for MongoQuotaTracker<'_> {
+    async fn spent(&self, key: &str, units: i64) {
+        if let Err(e) = self.mongo.add_quota_usage(self.day, key, units).await {
+            println!("Error recording quota usage: {}", e);
+        }
+    }
+
+    async fn exhausted(&self, key: &str) {
+        if let Err(e) = self.mongo.set_key_exhausted(self.day, key).await {
+            println!("Error recording exhausted API key {}: {}", key, e);
+        }
+    }
 }
 
 pub struct YoutubeClient {
-    client: reqwest::Client,
+    api: youtube_api::ApiClient,
+    /// Where quota usage is read and recorded.
+    mongo: crate::data::Mongo,
 }
 
 impl YoutubeClient {
-    pub fn new() -> Self {
-        let client = reqwest::Client::new();
-        Self { client }
+    pub fn new(mongo: crate::data::Mongo) -> Self {
+        Self {
+            api: youtube_api::ApiClient::from_env(),
+            mongo,
+        }
+    }
+
+    pub async fn get_video_metadata(&self, video_id: &str) -> Result<VideoMetadata, YoutubeError> {
+        self.list_videos(&[video_id], Priority::High)
+            .await?
+            .into_iter()
+            .next()
+            .map(VideoMetadata::from)
+            .ok_or_else(|| YoutubeError::NotFound(video_id.to_string()))
     }
 
-    pub async fn get_video_metadata(
+    /// Look up many videos at once, `MAX_IDS_PER_REQUEST` per request.
+    ///
+    /// Duplicate ids are looked up once. Every id gets its own result: a failed request fails
+    /// only the ids it was for, and ids missing from the response are `NotFound`.
+    pub async fn get_videos_metadata(
         &self,
-        video_id: &str,
-    ) -> Result<VideoMetadata, Box<dyn std::error::Error>> {
-        let url = "https://youtube.googleapis.com/youtube/v3/videos";
-        let api_key = std::env::var("YOUTUBE_API_KEY")?;
+        video_ids: &[&str],
+        priority: Priority,
+    ) -> HashMap<String, Result<VideoMetadata, YoutubeError>> {
+        let mut pending: Vec<&str> = Vec::new();
+        for video_id in video_ids {
+            if !pending.contains(video_id) {
+                pending.push(video_id);
+            }
+        }
+
+        let mut results = HashMap::new();
+        for chunk in pending.chunks(MAX_IDS_PER_REQUEST) {
+            match self.list_videos(chunk, priority).await {
+                Ok(videos) => {
+                    for video in videos {
+                        results.insert(video.id.clone(), Ok(video.into()));
+                    }
+                    for video_id in chunk {
+                        results
+                            .entry(video_id.to_string())
+                            .or_insert_with(|| Err(YoutubeError::NotFound(video_id.to_string())));
+                    }
+                }
+                Err(e) => {
+                    for video_id in chunk {
+                        results.insert(video_id.to_string(), Err(e.clone()));
+                    }
+                }
+            }
+        }
+
+        results
+    }
+
+    /// Call `videos.list` for up to `MAX_IDS_PER_REQUEST` ids.
+    async fn list_videos(
+        &self,
+        video_ids: &[&str],
+        priority: Priority,
+    ) -> Result<Vec<Video>, YoutubeError> {
+        let ids = video_ids.join(",");
         let params = [
             (
                 "part",
                 "snippet,contentDetails,statistics,liveStreamingDetails",
             ),
-            ("id", video_id),
-            ("key", &api_key),
+            ("id", ids.as_str()),
         ];
-        let response = self.client.get(url).query(&params).send().await?;
-        let body = response.text().await?;
-        println!("Body: {}", body);
-
-        let json: serde_json::Value = serde_json::from_str(&body)?;
-        let items = json["items"].as_array().ok_or("No items")?;
-        let item = items.first().ok_or("No items")?;
-        let snippet = item["snippet"].as_object().ok_or("No snippet")?;
-
-        let title = snippet["title"].as_str().ok_or("No title")?;
-
-        let description = snippet["description"].as_str().ok_or("No description")?;
-        let channel_id = snippet["channelId"].as_str().ok_or("No channelId")?;
-        let channel_title = snippet["channelTitle"].as_str().ok_or("No channelTitle")?;
-
-        let live_streaming_details = item["liveStreamingDetails"]
-            .as_object()
-            .ok_or("No liveStreamingDetails")?;
-
-        // e.g. 2022-03-04T18:12:32Z
-        let livestream_start_dt = live_streaming_details["scheduledStartTime"]
-            .as_str()
-            .ok_or("No scheduledStartTime")?;
-
-        Ok(VideoMetadata {
-            title: title.to_string(),
-            description: description.to_string(),
-            channel_id: channel_id.to_string(),
-            channel_title: channel_title.to_string(),
-            // tags,
-            livestream_start_dt: chrono::DateTime::parse_from_rfc3339(livestream_start_dt)?
-                .to_utc(),
-        })
+        let body = self
+            .get("videos", &params, VIDEOS_LIST_COST, priority)
+            .await?;
+
+        let video_list: VideoListResponse =
+            serde_json::from_str(&body).map_err(|e| YoutubeError::Parse(e.to_string()))?;
+
+        Ok(video_list.items)
+    }
+
+    /// Call `endpoint` with the first API key that still has quota today, and return the
+    /// body of the response.
+    ///
+    /// A key that runs out of quota or hits the rate limit is not used again until the
+    /// quota resets. Low-priority requests are deferred once the budget is nearly spent.
+    async fn get(
+        &self,
+        endpoint: &str,
+        params: &[(&str, &str)],
+        cost: i64,
+        priority: Priority,
+    ) -> Result<String, YoutubeError> {
+        let api_keys = api_keys();
+        if api_keys.is_empty() {
+            return Err(YoutubeError::MissingApiKey);
+        }
+
+        let day = quota_day(Utc::now());
+        let usage = match self.mongo.get_quota_usage(&day).await {
+            Ok(usage) => usage,
+            Err(e) => {
+                println!("Error reading quota usage: {}", e);
+                Default::default()
+            }
+        };
+
+        if priority == Priority::Low {
+            let budget = QuotaBudget::from_env();
+            if usage.units + cost > budget.low_priority_units {
+                println!(
+                    "Deferring low-priority {} request, {}/{} quota units spent",
+                    endpoint, usage.units, budget.units
+                );
+                if let Err(e) = self.mongo.add_deferred_request(&day).await {
+                    println!("Error recording deferred request: {}", e);
+                }
+                return Err(YoutubeError::Deferred);
+            }
+        }
+
+        let api_keys: Vec<_> = api_keys
+            .into_iter()
+            .filter(|api_key| {
+                usage
+                    .keys
+                    .get(&key_label(api_key))
+                    .is_none_or(|key_usage| key_usage.exhausted_at.is_none())
+            })
+            .collect();
+
+        self.api
+            .get(
+                endpoint,
+                params,
+                cost,
+                &api_keys,
+                &MongoQuotaTracker {
+                    mongo: &self.mongo,
+                    day: &day,
+                },
+            )
+            .await
+    }
+}
+
+/// Parse an ISO 8601 duration such as `PT1H2M3S` or `P1DT2H`.
+fn parse_duration(duration: &str) -> Option<chrono::Duration> {
+    let duration = duration.strip_prefix('P')?;
+    let (days, time) = duration.split_once('T').unwrap_or((duration, ""));
+
+    let mut seconds = 0;
+    for (part, units) in [
+        (days, &[('W', 7 * 24 * 60 * 60), ('D', 24 * 60 * 60)][..]),
+        (time, &[('H', 60 * 60), ('M', 60), ('S', 1)][..]),
+    ] {
+        let mut number = String::new();
+        for c in part.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+
+            let (_, unit) = units.iter().find(|(designator, _)| *designator == c)?;
+            seconds += number.parse::<i64>().ok()? * unit;
+            number.clear();
+        }
+        if !number.is_empty() {
+            return None;
+        }
+    }
+
+    Some(chrono::Duration::seconds(seconds))
+}
+
+/// Deserialize a count, which the API sends as a string.
+fn de_count<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let count: Option<String> = Deserialize::deserialize(deserializer)?;
+
+    count
+        .map(|count| count.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(video_id: &str) -> serde_json::Value {
+        let path = format!(
+            "{}/fixtures/youtube/videos/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            video_id
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn video_metadata(video: serde_json::Value) -> VideoMetadata {
+        serde_json::from_value::<Video>(video).unwrap().into()
+    }
+
+    fn utc(dt: &str) -> Option<DateTime<Utc>> {
+        Some(dt.parse().unwrap())
+    }
+
+    #[test]
+    fn video_metadata_reads_an_upcoming_stream() {
+        let metadata = video_metadata(fixture("upcoming001"));
+
+        assert_eq!(metadata.id, "upcoming001");
+        assert_eq!(metadata.channel_id, "UCmockchannel000000000001");
+        assert_eq!(
+            metadata.live_broadcast_content,
+            LiveBroadcastContent::Upcoming
+        );
+        assert_eq!(metadata.livestream_start_dt, utc("2030-01-01T12:00:00Z"));
+        assert_eq!(metadata.actual_start_time, None);
+        assert_eq!(metadata.concurrent_viewers, None);
+        assert_eq!(metadata.tags, vec!["hololive", "mock"]);
+        assert_eq!(metadata.thumbnails.len(), 2);
+        let high = &metadata.thumbnails["high"];
+        assert_eq!(
+            high.url,
+            "https://i.ytimg.com/vi/upcoming001/hqdefault_live.jpg"
+        );
+        assert_eq!((high.width, high.height), (Some(480), Some(360)));
+        assert_eq!(metadata.duration, Some(chrono::Duration::zero()));
+    }
+
+    #[test]
+    fn video_metadata_reads_a_live_stream() {
+        let metadata = video_metadata(fixture("live0000001"));
+
+        assert_eq!(metadata.live_broadcast_content, LiveBroadcastContent::Live);
+        assert_eq!(metadata.livestream_start_dt, utc("2023-09-01T10:00:00Z"));
+        assert_eq!(metadata.actual_start_time, utc("2023-09-01T10:04:12Z"));
+        assert_eq!(metadata.actual_end_time, None);
+        assert_eq!(metadata.concurrent_viewers, Some(1234));
+        assert_eq!(metadata.statistics.view_count, Some(1520));
+        assert!(metadata.tags.is_empty());
+    }
+
+    #[test]
+    fn video_metadata_reads_an_ended_stream() {
+        let metadata = video_metadata(fixture("ended000001"));
+
+        assert_eq!(
+            metadata.live_broadcast_content,
+            LiveBroadcastContent::NotLive
+        );
+        assert_eq!(metadata.actual_start_time, utc("2023-08-31T10:01:00Z"));
+        assert_eq!(metadata.actual_end_time, utc("2023-08-31T11:33:05Z"));
+        assert_eq!(metadata.concurrent_viewers, None);
+        assert!(metadata.thumbnails.is_empty());
+        assert_eq!(
+            metadata.duration,
+            Some(chrono::Duration::seconds(60 * 60 + 32 * 60 + 5))
+        );
+    }
+
+    #[test]
+    fn video_metadata_has_no_start_without_live_streaming_details() {
+        let mut video = fixture("ended000001");
+        video
+            .as_object_mut()
+            .unwrap()
+            .remove("liveStreamingDetails");
+
+        let metadata = video_metadata(video);
+
+        assert_eq!(metadata.livestream_start_dt, None);
+        assert_eq!(metadata.actual_start_time, None);
+        assert_eq!(metadata.actual_end_time, None);
+    }
+
+    #[test]
+    fn parse_duration_reads_hours_minutes_and_seconds() {
+        assert_eq!(
+            parse_duration("PT1H2M3S"),
+            Some(chrono::Duration::seconds(60 * 60 + 2 * 60 + 3))
+        );
+    }
+
+    #[test]
+    fn parse_duration_reads_days_before_time() {
+        assert_eq!(
+            parse_duration("P1DT2H"),
+            Some(chrono::Duration::seconds(26 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn parse_duration_reads_zero() {
+        assert_eq!(parse_duration("P0D"), Some(chrono::Duration::zero()));
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid_durations() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("1H"), None);
+        assert_eq!(parse_duration("PT1X"), None);
+        assert_eq!(parse_duration("PT12"), None);
+        assert_eq!(parse_duration("P1H"), None);
     }
 }
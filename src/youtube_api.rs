@@ -0,0 +1,294 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+
+/// An error returned by the YouTube Data API, or while talking to it.
+#[derive(Debug, Clone)]
+pub enum YoutubeError {
+    MissingApiKey,
+    /// The daily quota of the API key is used up.
+    QuotaExceeded(String),
+    /// Too many requests in a short time; worth retrying later.
+    RateLimitExceeded(String),
+    KeyInvalid(String),
+    Forbidden(String),
+    /// No video with this id, or it is private.
+    NotFound(String),
+    /// Any other error response.
+    Api {
+        code: u16,
+        reason: String,
+        message: String,
+    },
+    Http(String),
+    /// The response was not in the expected shape.
+    Parse(String),
+    /// A low-priority request was not sent because the day's quota budget is nearly spent.
+    Deferred,
+}
+
+impl std::fmt::Display for YoutubeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            YoutubeError::MissingApiKey => {
+                write!(f, "Missing `YOUTUBE_API_KEYS` or `YOUTUBE_API_KEY` env var")
+            }
+            YoutubeError::QuotaExceeded(message) => write!(f, "Quota exceeded: {}", message),
+            YoutubeError::RateLimitExceeded(message) => {
+                write!(f, "Rate limit exceeded: {}", message)
+            }
+            YoutubeError::KeyInvalid(message) => write!(f, "Invalid API key: {}", message),
+            YoutubeError::Forbidden(message) => write!(f, "Forbidden: {}", message),
+            YoutubeError::NotFound(id) => write!(f, "Not found: {}", id),
+            YoutubeError::Api {
+                code,
+                reason,
+                message,
+            } => write!(f, "YouTube API error {} ({}): {}", code, reason, message),
+            YoutubeError::Http(e) => write!(f, "Error calling the YouTube API: {}", e),
+            YoutubeError::Parse(e) => write!(f, "Unexpected YouTube API response: {}", e),
+            YoutubeError::Deferred => write!(f, "Deferred to save YouTube API quota"),
+        }
+    }
+}
+
+impl std::error::Error for YoutubeError {}
+
+impl From<reqwest::Error> for YoutubeError {
+    fn from(e: reqwest::Error) -> Self {
+        YoutubeError::Http(e.to_string())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    code: u16,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    errors: Vec<ErrorDetail>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    reason: String,
+}
+
+impl From<ErrorBody> for YoutubeError {
+    fn from(error: ErrorBody) -> Self {
+        let reason = error
+            .errors
+            .into_iter()
+            .next()
+            .map(|detail| detail.reason)
+            .unwrap_or_default();
+
+        match reason.as_str() {
+            "quotaExceeded" | "dailyLimitExceeded" => YoutubeError::QuotaExceeded(error.message),
+            "rateLimitExceeded" | "userRateLimitExceeded" => {
+                YoutubeError::RateLimitExceeded(error.message)
+            }
+            "keyInvalid" | "keyExpired" => YoutubeError::KeyInvalid(error.message),
+            "forbidden" => YoutubeError::Forbidden(error.message),
+            "notFound" | "videoNotFound" => YoutubeError::NotFound(error.message),
+            _ => YoutubeError::Api {
+                code: error.code,
+                reason,
+                message: error.message,
+            },
+        }
+    }
+}
+
+/// What an API key is called in `youtubeQuota` and in logs, so the key itself never is.
+///
+/// Taken from a hash of the whole key, so keys that share a suffix still get their own
+/// quota record.
+pub fn key_label(api_key: &str) -> String {
+    let digest = Sha256::digest(api_key.as_bytes());
+    format!("key-{}", &hex::encode(digest)[..8])
+}
+
+/// Where [`ApiClient::get`] records what its API keys spent. Keys are passed by their
+/// [`key_label`].
+pub trait QuotaTracker: Send + Sync {
+    /// Called after every request. Failed requests cost quota too.
+    fn spent(&self, _key: &str, _units: i64) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Called when a key ran out of quota or hit the rate limit, and should not be used
+    /// again until the quota resets.
+    fn exhausted(&self, _key: &str) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+/// Tracks nothing.
+impl QuotaTracker for () {}
+
+/// Calls the YouTube Data API, moving on to the next API key when one runs out of quota.
+pub struct ApiClient {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl ApiClient {
+    /// A client for the API under `base_url`, e.g. a [`crate::youtube_mock::MockYoutube`].
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.into(),
+        }
+    }
+
+    /// A client for the API under `YOUTUBE_API_BASE_URL`, or YouTube's when it is unset or
+    /// blank.
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("YOUTUBE_API_BASE_URL")
+                .ok()
+                .filter(|base_url| !base_url.trim().is_empty())
+                .unwrap_or_else(|| "https://youtube.googleapis.com/youtube/v3".to_string()),
+        )
+    }
+
+    /// Call `endpoint` with the first of `api_keys` that is not out of quota, and return the
+    /// body of the response.
+    ///
+    /// A key that runs out of quota or hits the rate limit is reported to `tracker` and the
+    /// next key is tried. Any other error is returned straight away.
+    pub async fn get(
+        &self,
+        endpoint: &str,
+        params: &[(&str, &str)],
+        cost: i64,
+        api_keys: &[String],
+        tracker: &impl QuotaTracker,
+    ) -> Result<String, YoutubeError> {
+        let url = format!("{}/{}", self.base_url.trim_end_matches('/'), endpoint);
+
+        let mut last_error = None;
+        for api_key in api_keys {
+            let key = key_label(api_key);
+            let result = self.send(&url, params, api_key).await;
+            tracker.spent(&key, cost).await;
+
+            match result {
+                Err(e @ (YoutubeError::QuotaExceeded(_) | YoutubeError::RateLimitExceeded(_))) => {
+                    println!("Not using API key {} until the quota resets: {}", key, e);
+                    tracker.exhausted(&key).await;
+                    last_error = Some(e);
+                }
+                result => return result,
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            YoutubeError::QuotaExceeded("Every API key is out of quota until the reset".to_string())
+        }))
+    }
+
+    async fn send(
+        &self,
+        url: &str,
+        params: &[(&str, &str)],
+        api_key: &str,
+    ) -> Result<String, YoutubeError> {
+        let response = self
+            .client
+            .get(url)
+            .query(params)
+            .query(&[("key", api_key)])
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        println!("Body: {}", body);
+
+        if !status.is_success() {
+            return Err(match serde_json::from_str::<ErrorResponse>(&body) {
+                Ok(error_response) => error_response.error.into(),
+                Err(_) => YoutubeError::Api {
+                    code: status.as_u16(),
+                    reason: String::new(),
+                    message: body,
+                },
+            });
+        }
+
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(reason: &str) -> YoutubeError {
+        let body = format!(
+            r#"{{"error": {{"code": 403, "message": "message", "errors": [{{"reason": "{}"}}]}}}}"#,
+            reason
+        );
+        serde_json::from_str::<ErrorResponse>(&body)
+            .unwrap()
+            .error
+            .into()
+    }
+
+    #[test]
+    fn error_body_maps_quota_exceeded() {
+        assert!(matches!(
+            error("quotaExceeded"),
+            YoutubeError::QuotaExceeded(_)
+        ));
+        assert!(matches!(
+            error("dailyLimitExceeded"),
+            YoutubeError::QuotaExceeded(_)
+        ));
+    }
+
+    #[test]
+    fn error_body_maps_rate_limit_exceeded() {
+        assert!(matches!(
+            error("rateLimitExceeded"),
+            YoutubeError::RateLimitExceeded(_)
+        ));
+        assert!(matches!(
+            error("userRateLimitExceeded"),
+            YoutubeError::RateLimitExceeded(_)
+        ));
+    }
+
+    #[test]
+    fn error_body_maps_key_invalid() {
+        assert!(matches!(error("keyInvalid"), YoutubeError::KeyInvalid(_)));
+        assert!(matches!(error("keyExpired"), YoutubeError::KeyInvalid(_)));
+    }
+
+    #[test]
+    fn error_body_maps_not_found() {
+        assert!(matches!(error("notFound"), YoutubeError::NotFound(_)));
+        assert!(matches!(error("videoNotFound"), YoutubeError::NotFound(_)));
+    }
+
+    #[test]
+    fn error_body_keeps_unknown_reasons() {
+        assert!(matches!(
+            error("backendError"),
+            YoutubeError::Api { code: 403, ref reason, .. } if reason == "backendError"
+        ));
+    }
+
+    #[test]
+    fn key_label_is_a_short_hash_of_the_key() {
+        assert_eq!(key_label("AIzaSyExample1234"), "key-fa313d65");
+        assert_ne!(key_label("AIzaSyOther1234"), key_label("AIzaSyExample1234"));
+    }
+}
@@ -0,0 +1,279 @@
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Stand-in for the YouTube Data API's `videos`, `channels` and `playlistItems` endpoints,
+/// answering from fixtures.
+///
+/// A fixture is the JSON resource YouTube would return for an id: a video or a channel, or
+/// for `playlistItems` the array of items in the playlist. Fixtures are added with
+/// [`MockYoutube::insert_fixture`] or read from `<fixtures_dir>/<endpoint>/<id>.json`. A
+/// fixture that is an error response (see [`error_response`]) is returned as the error
+/// instead, so quota and rate limit errors can be triggered per id. Every request costs its
+/// key one unit, and keys that spend their quota get `quotaExceeded`.
+pub struct MockYoutube {
+    fixtures_dir: Option<PathBuf>,
+    fixtures: Mutex<HashMap<(String, String), Value>>,
+    quota_per_key: Option<i64>,
+    units_spent: Mutex<HashMap<String, i64>>,
+}
+
+impl MockYoutube {
+    /// A mock that only answers with fixtures added through [`MockYoutube::insert_fixture`].
+    pub fn new() -> Self {
+        Self {
+            fixtures_dir: None,
+            fixtures: Mutex::new(HashMap::new()),
+            quota_per_key: None,
+            units_spent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Also read fixtures from `fixtures_dir`.
+    pub fn fixtures_dir(mut self, fixtures_dir: impl Into<PathBuf>) -> Self {
+        self.fixtures_dir = Some(fixtures_dir.into());
+        self
+    }
+
+    /// Answer with `quotaExceeded` once a key has spent `units`. Unlimited by default.
+    pub fn quota_per_key(mut self, units: i64) -> Self {
+        self.quota_per_key = Some(units);
+        self
+    }
+
+    /// Serve `value` for `id` on `endpoint` (`videos`, `channels` or `playlistItems`).
+    pub async fn insert_fixture(&self, endpoint: &str, id: &str, value: Value) {
+        self.fixtures
+            .lock()
+            .await
+            .insert((endpoint.to_string(), id.to_string()), value);
+    }
+
+    /// Units spent by `api_key` so far.
+    pub async fn units_spent(&self, api_key: &str) -> i64 {
+        self.units_spent
+            .lock()
+            .await
+            .get(api_key)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Serve the endpoints under the router's root, e.g. `/videos`. Point
+    /// `YOUTUBE_API_BASE_URL` at where it is served.
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/videos", get(videos_handler))
+            .route("/channels", get(channels_handler))
+            .route("/playlistItems", get(playlist_items_handler))
+            .layer(Extension(self))
+    }
+
+    async fn fixture(&self, endpoint: &str, id: &str) -> Option<Value> {
+        if let Some(value) = self
+            .fixtures
+            .lock()
+            .await
+            .get(&(endpoint.to_string(), id.to_string()))
+        {
+            return Some(value.clone());
+        }
+
+        // Ids come from the query string, so keep them from leaving the fixtures directory.
+        if id.contains(['/', '\\', '.']) {
+            return None;
+        }
+        let path = self
+            .fixtures_dir
+            .as_ref()?
+            .join(endpoint)
+            .join(format!("{}.json", id));
+        let contents = tokio::fs::read_to_string(&path).await.ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                println!("Invalid fixture {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Check the API key and charge it for the request.
+    async fn charge(&self, params: &HashMap<String, String>) -> Result<(), Response> {
+        let Some(api_key) = params.get("key") else {
+            return Err(error(
+                403,
+                "forbidden",
+                "The request is missing a valid API key.",
+            ));
+        };
+
+        let mut units_spent = self.units_spent.lock().await;
+        let spent = units_spent.entry(api_key.clone()).or_insert(0);
+        if self.quota_per_key.is_some_and(|quota| *spent >= quota) {
+            return Err(error(
+                403,
+                "quotaExceeded",
+                "The request cannot be completed because you have exceeded your quota.",
+            ));
+        }
+        *spent += 1;
+
+        Ok(())
+    }
+
+    /// Answer `videos` or `channels` with the fixture of every id in `id`. Ids without a
+    /// fixture are left out, as YouTube does.
+    async fn list(&self, endpoint: &str, kind: &str, params: HashMap<String, String>) -> Response {
+        if let Err(response) = self.charge(&params).await {
+            return response;
+        }
+
+        let mut items = Vec::new();
+        for id in params
+            .get("id")
+            .map(String::as_str)
+            .unwrap_or_default()
+            .split(',')
+            .filter(|id| !id.is_empty())
+        {
+            match self.fixture(endpoint, id).await {
+                Some(value) if value.get("error").is_some() => return error_fixture(value),
+                Some(value) => items.push(value),
+                None => {}
+            }
+        }
+
+        Json(json!({
+            "kind": format!("youtube#{}ListResponse", kind),
+            "pageInfo": {
+                "totalResults": items.len(),
+                "resultsPerPage": items.len(),
+            },
+            "items": items,
+        }))
+        .into_response()
+    }
+
+    /// Answer `playlistItems` with a page of the playlist's fixture. `pageToken` is the
+    /// offset of the page.
+    async fn playlist_items(&self, params: HashMap<String, String>) -> Response {
+        if let Err(response) = self.charge(&params).await {
+            return response;
+        }
+
+        let playlist_id = params
+            .get("playlistId")
+            .map(String::as_str)
+            .unwrap_or_default();
+        let items = match self.fixture("playlistItems", playlist_id).await {
+            Some(value) if value.get("error").is_some() => return error_fixture(value),
+            Some(Value::Array(items)) => items,
+            _ => return error(
+                404,
+                "playlistNotFound",
+                "The playlist identified with the request's playlistId parameter cannot be found.",
+            ),
+        };
+
+        let max_results = params
+            .get("maxResults")
+            .and_then(|max_results| max_results.parse().ok())
+            .unwrap_or(5usize)
+            .clamp(1, 50);
+        let offset = params
+            .get("pageToken")
+            .and_then(|page_token| page_token.parse().ok())
+            .unwrap_or(0usize);
+
+        let page: Vec<_> = items.iter().skip(offset).take(max_results).collect();
+        let mut response = json!({
+            "kind": "youtube#playlistItemListResponse",
+            "pageInfo": {
+                "totalResults": items.len(),
+                "resultsPerPage": max_results,
+            },
+            "items": page,
+        });
+        if offset + max_results < items.len() {
+            response["nextPageToken"] = json!((offset + max_results).to_string());
+        }
+        if offset > 0 {
+            response["prevPageToken"] = json!(offset.saturating_sub(max_results).to_string());
+        }
+
+        Json(response).into_response()
+    }
+}
+
+impl Default for MockYoutube {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An error response in YouTube's format, for use as a fixture.
+pub fn error_response(code: u16, reason: &str, message: &str) -> Value {
+    let domain = match reason {
+        "quotaExceeded" | "rateLimitExceeded" => "youtube.quota",
+        _ => "global",
+    };
+
+    json!({
+        "error": {
+            "code": code,
+            "message": message,
+            "errors": [
+                {
+                    "message": message,
+                    "domain": domain,
+                    "reason": reason,
+                }
+            ],
+        }
+    })
+}
+
+fn error(code: u16, reason: &str, message: &str) -> Response {
+    error_fixture(error_response(code, reason, message))
+}
+
+/// Answer with an error fixture, using its `error.code` as the status.
+fn error_fixture(value: Value) -> Response {
+    let status = value["error"]["code"]
+        .as_u64()
+        .and_then(|code| StatusCode::from_u16(code as u16).ok())
+        .unwrap_or(StatusCode::BAD_REQUEST);
+
+    (status, Json(value)).into_response()
+}
+
+async fn videos_handler(
+    Extension(mock): Extension<Arc<MockYoutube>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    mock.list("videos", "video", params).await
+}
+
+async fn channels_handler(
+    Extension(mock): Extension<Arc<MockYoutube>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    mock.list("channels", "channel", params).await
+}
+
+async fn playlist_items_handler(
+    Extension(mock): Extension<Arc<MockYoutube>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    mock.playlist_items(params).await
+}
@@ -0,0 +1,143 @@
+use hololive_livestream_notifier_rs::hub::LocalHub;
+use hololive_livestream_notifier_rs::pubsub::{
+    Delivery, DeliveryHandler, PubSub, SubscriptionState,
+};
+use reqwest::Url;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const CHANNEL_ID: &str = "UCmockchannel000000000001";
+
+/// Keeps every delivery it is handed.
+#[derive(Default)]
+struct RecordingHandler {
+    deliveries: Mutex<Vec<Delivery>>,
+}
+
+impl DeliveryHandler for RecordingHandler {
+    async fn deliver(
+        &self,
+        delivery: Delivery,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.deliveries.lock().await.push(delivery);
+        Ok(())
+    }
+}
+
+fn bind() -> (TcpListener, Url) {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    (listener, url)
+}
+
+fn serve(listener: TcpListener, router: axum::Router) {
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(router.into_make_service());
+    tokio::spawn(server);
+}
+
+/// A local hub and a subscriber, each served on an ephemeral port.
+async fn setup() -> (Arc<LocalHub>, Arc<PubSub>, Arc<RecordingHandler>) {
+    let (hub_listener, hub_url) = bind();
+    let hub = Arc::new(LocalHub::new(hub_url));
+    serve(hub_listener, Arc::clone(&hub).router());
+
+    let (callback_listener, callback_url) = bind();
+    let pubsub = Arc::new(PubSub::new(callback_url.join("pubsub").unwrap()));
+    let handler = Arc::new(RecordingHandler::default());
+    serve(
+        callback_listener,
+        Arc::clone(&pubsub).router(Arc::clone(&handler)),
+    );
+
+    (hub, pubsub, handler)
+}
+
+/// Subscribe to the channel's feed and wait for the hub to verify the subscription.
+async fn subscribe(hub: &LocalHub, pubsub: &PubSub) -> Url {
+    let topic = hub.topic_url(CHANNEL_ID);
+    pubsub.subscribe(topic.clone()).await.unwrap();
+
+    for _ in 0..50 {
+        let verified = pubsub.subscriptions().await.iter().any(|subscription| {
+            subscription.topic == topic
+                && subscription.intent.is_none()
+                && subscription.state == SubscriptionState::Verified
+        });
+        if verified {
+            return topic;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The hub did not verify the subscription to {}", topic);
+}
+
+#[tokio::test]
+async fn subscription_is_verified_through_the_discovered_hub() {
+    let (hub, pubsub, _handler) = setup().await;
+
+    let topic = subscribe(&hub, &pubsub).await;
+
+    let subscription = pubsub
+        .subscriptions()
+        .await
+        .into_iter()
+        .find(|subscription| subscription.topic == topic)
+        .unwrap();
+    assert_eq!(subscription.hub, hub.hub_url().as_str());
+    assert!(subscription.lease_expires_at.is_some());
+    assert_eq!(
+        hub.subscribers(CHANNEL_ID).await,
+        vec![pubsub.callback_url(&subscription.id)]
+    );
+}
+
+#[tokio::test]
+async fn signed_delivery_reaches_the_handler() {
+    let (hub, pubsub, handler) = setup().await;
+    let topic = subscribe(&hub, &pubsub).await;
+
+    let results = hub
+        .publish_entry(CHANNEL_ID, "upcoming001", "Upcoming stream")
+        .await;
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].1, Ok(reqwest::StatusCode::OK));
+    let deliveries = handler.deliveries.lock().await;
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].topic, topic);
+    assert!(String::from_utf8_lossy(&deliveries[0].body).contains("upcoming001"));
+    assert_eq!(pubsub.rejected_deliveries().invalid_signature, 0);
+}
+
+#[tokio::test]
+async fn delivery_with_a_wrong_signature_is_rejected() {
+    let (hub, pubsub, handler) = setup().await;
+    let topic = subscribe(&hub, &pubsub).await;
+    let subscription = pubsub
+        .subscriptions()
+        .await
+        .into_iter()
+        .find(|subscription| subscription.topic == topic)
+        .unwrap();
+
+    let response = reqwest::Client::new()
+        .post(pubsub.callback_url(&subscription.id))
+        .header("Content-Type", "application/atom+xml")
+        .header("Link", format!("<{}>; rel=self", topic))
+        .header(
+            "X-Hub-Signature",
+            "sha1=0000000000000000000000000000000000000000",
+        )
+        .body("<feed xmlns=\"http://www.w3.org/2005/Atom\"></feed>")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+    assert!(handler.deliveries.lock().await.is_empty());
+    assert_eq!(pubsub.rejected_deliveries().invalid_signature, 1);
+}
@@ -0,0 +1,168 @@
+use hololive_livestream_notifier_rs::youtube_api::{
+    key_label, ApiClient, QuotaTracker, YoutubeError,
+};
+use hololive_livestream_notifier_rs::youtube_mock::{error_response, MockYoutube};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Records what [`ApiClient::get`] reports, in order.
+#[derive(Default)]
+struct RecordingTracker {
+    spent: Mutex<Vec<(String, i64)>>,
+    exhausted: Mutex<Vec<String>>,
+}
+
+impl QuotaTracker for RecordingTracker {
+    async fn spent(&self, key: &str, units: i64) {
+        self.spent.lock().await.push((key.to_string(), units));
+    }
+
+    async fn exhausted(&self, key: &str) {
+        self.exhausted.lock().await.push(key.to_string());
+    }
+}
+
+/// Serve `mock` on an ephemeral port and return a client for it.
+fn serve(mock: Arc<MockYoutube>) -> ApiClient {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(mock.router().into_make_service());
+    tokio::spawn(server);
+
+    ApiClient::new(format!("http://{}", addr))
+}
+
+fn fixtures_dir() -> String {
+    format!("{}/fixtures/youtube", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn keys(keys: &[&str]) -> Vec<String> {
+    keys.iter().map(|key| key.to_string()).collect()
+}
+
+#[tokio::test]
+async fn moves_on_to_the_next_key_when_quota_is_exceeded() {
+    let mock = Arc::new(
+        MockYoutube::new()
+            .fixtures_dir(fixtures_dir())
+            .quota_per_key(1),
+    );
+    let client = serve(Arc::clone(&mock));
+    let api_keys = keys(&["first-key-aaaa", "second-key-bbbb"]);
+    let params = [("part", "snippet"), ("id", "upcoming001")];
+
+    // Spend the first key's quota.
+    client
+        .get("videos", &params, 1, &api_keys[..1], &())
+        .await
+        .unwrap();
+
+    let tracker = RecordingTracker::default();
+    let body = client
+        .get("videos", &params, 1, &api_keys, &tracker)
+        .await
+        .unwrap();
+
+    assert!(body.contains("upcoming001"));
+    assert_eq!(
+        *tracker.exhausted.lock().await,
+        vec![key_label(&api_keys[0])]
+    );
+    assert_eq!(
+        *tracker.spent.lock().await,
+        vec![(key_label(&api_keys[0]), 1), (key_label(&api_keys[1]), 1)]
+    );
+    assert_eq!(mock.units_spent(&api_keys[1]).await, 1);
+}
+
+#[tokio::test]
+async fn moves_on_to_the_next_key_when_rate_limited() {
+    let mock = Arc::new(MockYoutube::new().fixtures_dir(fixtures_dir()));
+    let client = serve(Arc::clone(&mock));
+    let api_keys = keys(&["first-key-aaaa", "second-key-bbbb"]);
+
+    let tracker = RecordingTracker::default();
+    let result = client
+        .get(
+            "videos",
+            &[("part", "snippet"), ("id", "ratelimit01")],
+            1,
+            &api_keys,
+            &tracker,
+        )
+        .await;
+
+    // The fixture rate limits every key, so both are tried and the last error returned.
+    assert!(matches!(result, Err(YoutubeError::RateLimitExceeded(_))));
+    assert_eq!(
+        *tracker.exhausted.lock().await,
+        vec![key_label(&api_keys[0]), key_label(&api_keys[1])]
+    );
+    assert_eq!(mock.units_spent(&api_keys[0]).await, 1);
+    assert_eq!(mock.units_spent(&api_keys[1]).await, 1);
+}
+
+#[tokio::test]
+async fn gives_up_when_every_key_is_out_of_quota() {
+    let mock = Arc::new(MockYoutube::new().fixtures_dir(fixtures_dir()));
+    let client = serve(Arc::clone(&mock));
+    let api_keys = keys(&["first-key-aaaa", "second-key-bbbb"]);
+
+    let tracker = RecordingTracker::default();
+    let result = client
+        .get(
+            "videos",
+            &[("part", "snippet"), ("id", "quota000001")],
+            1,
+            &api_keys,
+            &tracker,
+        )
+        .await;
+
+    // The fixture is a quotaExceeded error, which every key gets.
+    assert!(matches!(result, Err(YoutubeError::QuotaExceeded(_))));
+    assert_eq!(
+        *tracker.exhausted.lock().await,
+        vec![key_label(&api_keys[0]), key_label(&api_keys[1])]
+    );
+    assert_eq!(
+        *tracker.spent.lock().await,
+        vec![(key_label(&api_keys[0]), 1), (key_label(&api_keys[1]), 1)]
+    );
+}
+
+#[tokio::test]
+async fn returns_other_errors_without_trying_the_next_key() {
+    let mock = Arc::new(MockYoutube::new());
+    mock.insert_fixture(
+        "videos",
+        "private0001",
+        error_response(404, "videoNotFound", "The video cannot be found."),
+    )
+    .await;
+    let client = serve(Arc::clone(&mock));
+    let api_keys = keys(&["first-key-aaaa", "second-key-bbbb"]);
+
+    let tracker = RecordingTracker::default();
+    let result = client
+        .get("videos", &[("id", "private0001")], 1, &api_keys, &tracker)
+        .await;
+
+    assert!(matches!(result, Err(YoutubeError::NotFound(_))));
+    assert!(tracker.exhausted.lock().await.is_empty());
+    assert_eq!(mock.units_spent(&api_keys[1]).await, 0);
+}
+
+#[tokio::test]
+async fn reports_quota_exceeded_without_keys() {
+    let client = serve(Arc::new(MockYoutube::new()));
+
+    let result = client
+        .get("videos", &[("id", "upcoming001")], 1, &[], &())
+        .await;
+
+    assert!(matches!(result, Err(YoutubeError::QuotaExceeded(_))));
+}